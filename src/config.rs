@@ -0,0 +1,269 @@
+//! Layered CLI configuration.
+//!
+//! Configuration values are resolved from the following sources, in order of
+//! precedence:
+//!
+//! 1. Command-line flags.
+//! 2. Environment variables (`DCS_BACKEND_URL`).
+//! 3. A `.dcs.toml` file at the root of the student's repository.
+//! 4. A user-level `config.toml` in the DotCodeSchool config directory.
+//! 5. Compiled-in defaults.
+//!
+//! Every network call made by the CLI should resolve its endpoint through
+//! [`Config`] rather than reading constants directly.
+
+use std::{
+    fmt::Display,
+    path::{Path, PathBuf},
+};
+
+use serde::Deserialize;
+use thiserror::Error;
+
+use crate::constants::BACKEND_URL;
+
+pub const ENV_BACKEND_URL: &str = "DCS_BACKEND_URL";
+pub const PATH_CONFIG_REPO: &str = "./.dcs.toml";
+const DIR_CONFIG_USER: &str = "dotcodeschool";
+const FILE_CONFIG_USER: &str = "config.toml";
+
+#[derive(Error, Debug)]
+pub enum ConfigError {
+    #[error("failed to read config file at '{0}': {1}")]
+    ConfigRead(String, String),
+    #[error("invalid config file at '{0}': {1}")]
+    ConfigFmt(String, String),
+}
+
+/// Where a configuration value was resolved from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConfigSource {
+    Flag,
+    Env,
+    Repo(PathBuf),
+    User(PathBuf),
+    Default,
+}
+
+impl Display for ConfigSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConfigSource::Flag => write!(f, "--backend-url"),
+            ConfigSource::Env => write!(f, "{ENV_BACKEND_URL}"),
+            ConfigSource::Repo(path) | ConfigSource::User(path) => {
+                write!(f, "{}", path.display())
+            }
+            ConfigSource::Default => write!(f, "default"),
+        }
+    }
+}
+
+/// Contents of a `.dcs.toml` or user-level `config.toml` file. All fields are
+/// optional so that each layer only overrides what it sets.
+#[derive(Deserialize, Debug, Default)]
+struct ConfigFile {
+    backend_url: Option<String>,
+}
+
+/// Effective CLI configuration, once all layers have been resolved.
+#[derive(Debug, Clone)]
+pub struct Config {
+    backend_url: String,
+    backend_url_source: ConfigSource,
+}
+
+impl Config {
+    /// Resolves the effective configuration.
+    ///
+    /// * `backend_url`: value of the `--backend-url` flag, if any.
+    pub fn load(backend_url: Option<String>) -> Result<Self, ConfigError> {
+        log::debug!("Resolving configuration");
+
+        Self::resolve(
+            backend_url,
+            std::env::var(ENV_BACKEND_URL).ok(),
+            PathBuf::from(PATH_CONFIG_REPO),
+            Self::path_user(),
+        )
+    }
+
+    /// Resolves the configuration from the value of each layer, in order of
+    /// precedence.
+    fn resolve(
+        backend_url: Option<String>,
+        env: Option<String>,
+        path_repo: PathBuf,
+        path_user: Option<PathBuf>,
+    ) -> Result<Self, ConfigError> {
+        if let Some(backend_url) = backend_url {
+            return Ok(Self::new(backend_url, ConfigSource::Flag));
+        }
+
+        if let Some(backend_url) = env {
+            if !backend_url.is_empty() {
+                return Ok(Self::new(backend_url, ConfigSource::Env));
+            }
+        }
+
+        if let Some(ConfigFile { backend_url: Some(backend_url) }) =
+            Self::load_file(&path_repo)?
+        {
+            return Ok(Self::new(backend_url, ConfigSource::Repo(path_repo)));
+        }
+
+        if let Some(path_user) = path_user {
+            if let Some(ConfigFile { backend_url: Some(backend_url) }) =
+                Self::load_file(&path_user)?
+            {
+                return Ok(Self::new(
+                    backend_url,
+                    ConfigSource::User(path_user),
+                ));
+            }
+        }
+
+        Ok(Self::new(BACKEND_URL.to_string(), ConfigSource::Default))
+    }
+
+    /// Base URL of the DotCodeSchool backend API, without a trailing slash.
+    pub fn backend_url(&self) -> &str {
+        &self.backend_url
+    }
+
+    pub fn backend_url_source(&self) -> &ConfigSource {
+        &self.backend_url_source
+    }
+
+    fn new(backend_url: String, backend_url_source: ConfigSource) -> Self {
+        let backend_url = backend_url.trim_end_matches('/').to_string();
+        log::debug!("Using backend '{backend_url}' ({backend_url_source})");
+
+        Self { backend_url, backend_url_source }
+    }
+
+    fn path_user() -> Option<PathBuf> {
+        dirs::config_dir()
+            .map(|dir| dir.join(DIR_CONFIG_USER).join(FILE_CONFIG_USER))
+    }
+
+    fn load_file(path: &Path) -> Result<Option<ConfigFile>, ConfigError> {
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        log::debug!("Loading config file at '{}'", path.display());
+
+        let content = std::fs::read_to_string(path).map_err(|err| {
+            ConfigError::ConfigRead(path.display().to_string(), err.to_string())
+        })?;
+
+        toml::from_str(&content).map(Some).map_err(|err| {
+            ConfigError::ConfigFmt(path.display().to_string(), err.to_string())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Directory holding a repository and a user config file, each setting
+    /// `backend_url` if given.
+    fn layers(
+        name: &str,
+        repo: Option<&str>,
+        user: Option<&str>,
+    ) -> (PathBuf, PathBuf) {
+        let dir = std::env::temp_dir()
+            .join(format!("dcs-config-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let path_repo = dir.join(".dcs.toml");
+        let path_user = dir.join(FILE_CONFIG_USER);
+        for (path, backend_url) in [(&path_repo, repo), (&path_user, user)] {
+            if let Some(backend_url) = backend_url {
+                let content = format!("backend_url = \"{backend_url}\"\n");
+                std::fs::write(path, content).unwrap();
+            }
+        }
+
+        (path_repo, path_user)
+    }
+
+    fn resolve(
+        flag: Option<&str>,
+        env: Option<&str>,
+        (path_repo, path_user): &(PathBuf, PathBuf),
+    ) -> (String, ConfigSource) {
+        let config = Config::resolve(
+            flag.map(str::to_string),
+            env.map(str::to_string),
+            path_repo.clone(),
+            Some(path_user.clone()),
+        )
+        .unwrap();
+
+        (config.backend_url, config.backend_url_source)
+    }
+
+    #[test]
+    fn resolves_layers_in_order() {
+        let paths = layers("order", Some("http://repo"), Some("http://user"));
+        let (path_repo, path_user) = paths.clone();
+
+        assert_eq!(
+            resolve(Some("http://flag/"), Some("http://env"), &paths),
+            ("http://flag".to_string(), ConfigSource::Flag)
+        );
+        assert_eq!(
+            resolve(None, Some("http://env"), &paths),
+            ("http://env".to_string(), ConfigSource::Env)
+        );
+        assert_eq!(
+            resolve(None, Some(""), &paths),
+            ("http://repo".to_string(), ConfigSource::Repo(path_repo.clone()))
+        );
+
+        std::fs::remove_file(&path_repo).unwrap();
+        assert_eq!(
+            resolve(None, None, &paths),
+            ("http://user".to_string(), ConfigSource::User(path_user.clone()))
+        );
+
+        std::fs::remove_file(&path_user).unwrap();
+        assert_eq!(
+            resolve(None, None, &paths),
+            (BACKEND_URL.to_string(), ConfigSource::Default)
+        );
+
+        std::fs::remove_dir_all(path_repo.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn skips_files_without_backend() {
+        let paths = layers("partial", None, Some("http://user"));
+        let (path_repo, path_user) = paths.clone();
+        std::fs::write(&path_repo, "").unwrap();
+
+        assert_eq!(
+            resolve(None, None, &paths),
+            ("http://user".to_string(), ConfigSource::User(path_user))
+        );
+
+        std::fs::remove_dir_all(path_repo.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn rejects_invalid_files() {
+        let paths = layers("invalid", None, None);
+        let (path_repo, path_user) = paths.clone();
+        std::fs::write(&path_repo, "backend_url = ").unwrap();
+
+        let result =
+            Config::resolve(None, None, path_repo.clone(), Some(path_user));
+        assert!(matches!(result, Err(ConfigError::ConfigFmt(..))));
+
+        std::fs::remove_dir_all(path_repo.parent().unwrap()).unwrap();
+    }
+}
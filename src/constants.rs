@@ -1,2 +1,4 @@
 pub const BACKEND_URL: &str = "https://backend.dotcodeschool.com/api/v0";
+/// Log file used if the user has no data directory.
 pub const LOG: &str = "./.dcs.log";
+pub const PATH_TESTER_DEFINITION: &str = "./tester-definition.yml";
@@ -1,5 +1,5 @@
 use colored::Colorize;
-use std::{fmt::Display, os::unix::fs::MetadataExt};
+use std::{fmt::Display, path::Path, process::Command};
 
 use blake2::{
     digest::{Update, VariableOutput},
@@ -7,19 +7,38 @@ use blake2::{
 };
 use indexmap::IndexMap;
 use parity_scale_codec::{Decode, Encode};
+use serde::{Deserialize, Serialize};
+use sled::transaction::{
+    ConflictableTransactionResult, TransactionError, TransactionalTree,
+};
 use thiserror::Error;
 
 use crate::{
-    parsing::{CourseMetaData, MetadataError, TestResult},
+    migration::db_migrate,
+    parsing::{
+        v1::{JsonExpectedV1, JsonStdinV1},
+        CourseMetaData, MetadataError, TestOutput, TestResult,
+    },
+    runner::{
+        expect::output_check,
+        failures::failures_parse,
+        process::{run_limited, ProcessOutcome},
+        sandbox::Sandbox,
+    },
     str_res::OPTIONAL,
 };
 
+/// Location of the database before it was moved to the data directory, still
+/// used if the user has no data directory.
 pub const PATH_DB: &str = "./db";
-pub const KEY_TIME: &[u8] = b"time_last_modified";
+pub const KEY_HASH: &[u8] = b"hash";
+pub const KEY_VERSION: &[u8] = b"version";
 pub const KEY_TESTS: &[u8] = b"tests";
 pub const KEY_STAGGERED: &[u8] = b"staggered";
 pub const KEY_METADATA: &[u8] = b"metadata";
-const HASH_SIZE: usize = 2;
+pub const TREE_OUTBOX: &str = "outbox";
+pub const TREE_RUNS: &str = "runs";
+const HASH_SIZE_CONTENT: usize = 32;
 
 #[derive(Error, Debug)]
 pub enum DbError {
@@ -27,75 +46,278 @@ pub enum DbError {
     DbOpen(String, String),
     #[error("failed to open tree at '{0}': {1}")]
     DbOpenTree(String, String),
-    #[error(
-        "failed to check for database update, could not open file at {0}: {1}"
-    )]
-    DbUpdateCheck(String, String),
     #[error("failed to retrieve value at key '{0}': {1}")]
     DbGet(String, String),
     #[error("failed to insert value at key '{0}': {1}")]
     DbInsert(String, String),
     #[error("failed to decode data stored at key '{0}': {1}")]
     DecodeError(String, String),
+    #[error("failed to update tests: {0}")]
+    DbUpdate(String),
+    #[error("failed to migrate database to version {0}: {1}")]
+    DbMigration(u32, String),
+    #[error(
+        "database version {0} is not supported, this version of dotcodeschool \
+         supports up to version {1}: please update dotcodeschool"
+    )]
+    DbVersion(u32, u32),
     #[error("failed to retrieve course metadata")]
     MetadataError(#[from] MetadataError),
 }
 
-#[derive(Encode, Decode, Debug, Clone)]
+#[derive(Encode, Decode, Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "snake_case")]
 pub enum ValidationState {
     Unknown,
     Pass,
     Fail,
+    Timeout,
+    /// The test was not run as one of its dependencies failed.
+    Blocked,
 }
 
-#[derive(Encode, Decode, Debug, Clone)]
+#[derive(Encode, Decode, Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "snake_case")]
 pub enum PathLink {
     Link(String),
     LinkOptional(String),
 }
 
-#[derive(Encode, Decode, Debug, Clone)]
+/// Differences between the tests stored in db and those of an updated course.
+#[derive(Debug, Default, Clone)]
+pub struct CourseUpdate {
+    pub added: usize,
+    pub changed: usize,
+    pub removed: usize,
+}
+
+impl CourseUpdate {
+    pub fn is_empty(&self) -> bool {
+        self.added == 0 && self.changed == 0 && self.removed == 0
+    }
+}
+
+impl Display for CourseUpdate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let Self { added, changed, removed } = self;
+        let plural = |count: usize| if count == 1 { "" } else { "s" };
+
+        let parts =
+            [(added, "added"), (changed, "changed"), (removed, "removed")]
+                .into_iter()
+                .filter(|(count, _)| **count > 0)
+                .enumerate()
+                .map(|(index, (count, what))| match index {
+                    0 => format!("{count} test{} {what}", plural(*count)),
+                    _ => format!("{count} {what}"),
+                })
+                .collect::<Vec<_>>();
+
+        write!(f, "course updated: {}", parts.join(", "))
+    }
+}
+
+/// Resource limits enforced when running a test.
+///
+/// * `timeout`: maximum test duration, in seconds.
+/// * `max_memory`: maximum virtual memory of the test process, in MiB.
+/// * `max_output_bytes`: maximum size of `stdout` and `stderr`, in bytes.
+/// * `max_processes`: maximum number of processes of the user.
+#[derive(Encode, Decode, Serialize, Deserialize, Debug, Clone, Default)]
+pub struct TestLimits {
+    pub timeout: Option<u64>,
+    pub max_memory: Option<u64>,
+    pub max_output_bytes: Option<u64>,
+    pub max_processes: Option<u64>,
+}
+
+/// Input written to the `stdin` of a test. Files are relative to the tester
+/// workspace and read each time the test is run.
+#[derive(Encode, Decode, Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "snake_case")]
+pub enum TestStdin {
+    Inline(String),
+    File(String),
+}
+
+/// Output a test is expected to write to `stdout` or `stderr`. Golden files
+/// are relative to the tester workspace.
+#[derive(Encode, Decode, Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "snake_case")]
+pub enum TestExpected {
+    Exact(String),
+    Regex(String),
+    File(String),
+}
+
+/// Language of the test harness used by a course, whose output is parsed to
+/// summarize test failures.
+#[derive(Encode, Decode, Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "snake_case")]
+pub enum TestLanguage {
+    Rust,
+    Go,
+}
+
+impl From<JsonStdinV1> for TestStdin {
+    fn from(stdin: JsonStdinV1) -> Self {
+        match stdin {
+            JsonStdinV1::Inline(input) => Self::Inline(input),
+            JsonStdinV1::File { file } => Self::File(file),
+        }
+    }
+}
+
+impl From<JsonExpectedV1> for TestExpected {
+    fn from(expected: JsonExpectedV1) -> Self {
+        match expected {
+            JsonExpectedV1::Exact(text) => Self::Exact(text),
+            JsonExpectedV1::Regex { regex } => Self::Regex(regex),
+            JsonExpectedV1::File { file } => Self::File(file),
+        }
+    }
+}
+
+#[derive(Encode, Decode, Serialize, Deserialize, Debug, Clone)]
 pub struct TestState {
     pub name: String,
     pub slug: String,
     pub message_on_success: String,
     pub message_on_fail: String,
     pub cmd: Vec<String>,
+    /// Why `cmd` could not be parsed from the tester definition, reported
+    /// when the test is run.
+    pub cmd_error: Option<String>,
     pub path: Vec<PathLink>,
     pub passed: ValidationState,
     pub optional: bool,
+    pub section_slug: String,
     pub lesson_slug: String,
+    pub limits: TestLimits,
+    pub independent: bool,
+    pub env: Vec<(String, String)>,
+    pub cwd: Option<String>,
+    pub stdin: Option<TestStdin>,
+    pub expected_stdout: Option<TestExpected>,
+    pub expected_stderr: Option<TestExpected>,
+    pub language: Option<TestLanguage>,
+    /// Ids of the tests which must pass before this test is run, see
+    /// [`TestState::id`].
+    pub depends_on: Vec<String>,
+    /// Depth of this test in the dependency graph. Staggered mode unlocks
+    /// tests one stage at a time.
+    pub stage: u32,
 }
 
 impl TestState {
-    pub fn run(&self, target: &str) -> TestResult {
+    /// Identifies this test within its course, as 'lesson/test'. Slugs are
+    /// only unique within a lesson.
+    pub fn id(&self) -> String {
+        format!("{}/{}", self.lesson_slug, self.slug)
+    }
+
+    /// Runs this test in the `target` workspace, isolated from the rest of
+    /// the system if a `sandbox` is provided.
+    pub fn run(&self, target: &str, sandbox: Option<&Sandbox>) -> TestResult {
         log::debug!("Running test: '{:?}", self.cmd);
         log::debug!("Test location: '{:?}", target);
 
-        let output = std::process::Command::new(&self.cmd[0])
-            .args(self.cmd[1..].iter())
-            .current_dir(target)
-            .output();
-        let output = match output {
+        if let Some(err) = &self.cmd_error {
+            return TestResult::Fail(TestOutput::error(&format!(
+                "invalid test command: {err}"
+            )));
+        }
+
+        let Some((program, args)) = self.cmd.split_first() else {
+            return TestResult::Fail(TestOutput::error("invalid test command"));
+        };
+
+        let stdin = match &self.stdin {
+            Some(TestStdin::Inline(input)) => Some(input.clone().into_bytes()),
+            Some(TestStdin::File(file)) => {
+                match std::fs::read(Path::new(target).join(file)) {
+                    Ok(input) => Some(input),
+                    Err(err) => {
+                        return TestResult::Fail(TestOutput::error(&format!(
+                            "could not read stdin from '{file}': {err}"
+                        )));
+                    }
+                }
+            }
+            None => None,
+        };
+
+        let cwd = match &self.cwd {
+            Some(cwd) => Path::new(target).join(cwd),
+            None => Path::new(target).to_path_buf(),
+        };
+
+        let mut command = Command::new(program);
+        command
+            .args(args)
+            .envs(self.env.iter().map(|(key, value)| (key, value)))
+            .current_dir(cwd);
+
+        if let Some(sandbox) = sandbox {
+            if let Err(err) = sandbox.apply(&mut command) {
+                return TestResult::Fail(TestOutput::error(&err.to_string()));
+            }
+        }
+
+        let output = match run_limited(command, &self.limits, stdin) {
             Ok(output) => output,
-            Err(_) => {
-                return TestResult::Fail("could not execute test".to_string());
+            Err(err) => {
+                return TestResult::Fail(TestOutput::error(&format!(
+                    "could not execute test: {err}"
+                )));
             }
         };
 
         log::debug!("Test executed successfully!");
 
-        match output.status.success() {
-            true => TestResult::Pass(format!(
-                "\n{}\n{}",
-                String::from_utf8_lossy(&output.stderr),
-                String::from_utf8_lossy(&output.stdout)
-            )),
-            false => TestResult::Fail(format!(
-                "\n{}\n{}",
-                String::from_utf8_lossy(&output.stderr),
-                String::from_utf8_lossy(&output.stdout)
-            )),
+        let mut text = TestOutput {
+            stdout: String::from_utf8_lossy(&output.stdout).to_string(),
+            stderr: String::from_utf8_lossy(&output.stderr).to_string(),
+            duration: output.duration,
+            mismatches: vec![],
+            failures: vec![],
+        };
+
+        if let ProcessOutcome::Exited(_) = output.outcome {
+            let expected = [
+                ("stdout", &self.expected_stdout, &text.stdout),
+                ("stderr", &self.expected_stderr, &text.stderr),
+            ];
+
+            text.mismatches = expected
+                .into_iter()
+                .filter_map(|(stream, expected, actual)| {
+                    output_check(stream, expected.as_ref()?, actual, target)
+                })
+                .collect();
+        }
+
+        if let Some(language) = &self.language {
+            text.failures = failures_parse(language, &text.stdout);
+        }
+
+        match output.outcome {
+            ProcessOutcome::Exited(status)
+                if status.success() && text.mismatches.is_empty() =>
+            {
+                TestResult::Pass(text)
+            }
+            ProcessOutcome::Exited(_) => TestResult::Fail(text),
+            ProcessOutcome::TimedOut(timeout) => {
+                TestResult::Timeout(text, timeout)
+            }
+            ProcessOutcome::OutputExceeded(max_output_bytes) => {
+                text.stderr.push_str(&format!(
+                    "\n⚠ output exceeded {max_output_bytes} bytes, test was \
+                     stopped"
+                ));
+                TestResult::Fail(text)
+            }
         }
     }
 
@@ -181,19 +403,20 @@ impl Display for TestState {
     }
 }
 
-pub fn hash(words: &[&str]) -> String {
-    let phrase = words.join("");
-
-    let mut hasher = Blake2bVar::new(HASH_SIZE).unwrap();
-    let mut hash = [0; HASH_SIZE];
+pub fn db_open(
+    path_db: &str,
+    path_course: &str,
+) -> Result<(sled::Db, sled::Tree), DbError> {
+    let (db, tree) = db_open_raw(path_db, path_course)?;
 
-    hasher.update(phrase.as_bytes());
-    hasher.finalize_variable(&mut hash).unwrap();
+    db_migrate(&tree)?;
 
-    hex::encode(hash)
+    Ok((db, tree))
 }
 
-pub fn db_open(
+/// Opens the database as it is, without migrating it to the current schema
+/// version, so that it can be inspected without being modified.
+pub fn db_open_raw(
     path_db: &str,
     path_course: &str,
 ) -> Result<(sled::Db, sled::Tree), DbError> {
@@ -207,64 +430,332 @@ pub fn db_open(
     Ok((db, tree))
 }
 
-pub fn db_should_update(
-    tree: &sled::Tree,
-    path: &str,
-) -> Result<bool, DbError> {
-    let metadata = std::fs::metadata(path).map_err(|err| {
-        DbError::DbUpdateCheck(path.to_string(), err.to_string())
-    })?;
+/// Fingerprint of `content`, used to detect changes to the course.
+pub fn hash_content(content: &[&[u8]]) -> String {
+    let mut hasher = Blake2bVar::new(HASH_SIZE_CONTENT).unwrap();
+    let mut hash = [0; HASH_SIZE_CONTENT];
 
-    let time_last_modified = metadata.mtime();
-    let time_store = tree
-        .get(KEY_TIME)
-        .map_err(|err| DbError::DbGet(hex::encode(KEY_TIME), err.to_string()))?
-        .map(|bytes| i64::decode(&mut &bytes[..]).unwrap());
+    for bytes in content {
+        hasher.update(bytes);
+    }
+    hasher.finalize_variable(&mut hash).unwrap();
 
-    // TODO: replace this with `fetch_and_update`
-    tree.insert(KEY_TIME, time_last_modified.encode()).map_err(|err| {
-        DbError::DbInsert(hex::encode(KEY_TIME), err.to_string())
-    })?;
+    hex::encode(hash)
+}
 
-    let should_update = match time_store {
-        Some(time_store) => time_last_modified > time_store,
-        None => true,
-    };
+/// Whether the tests stored in db are out of date, that is if they were stored
+/// for a course with a different `hash`.
+pub fn db_should_update(
+    tree: &sled::Tree,
+    hash: &str,
+) -> Result<bool, DbError> {
+    let hash_store = tree
+        .get(KEY_HASH)
+        .map_err(|err| DbError::DbGet(hex::encode(KEY_HASH), err.to_string()))?
+        .and_then(|bytes| String::decode(&mut &bytes[..]).ok());
 
-    Ok(should_update)
+    Ok(hash_store.as_deref() != Some(hash))
 }
 
+/// Updates the tests stored in db to those of the current course.
+///
+/// Tests are matched with the tests already stored in db by lesson and slug,
+/// so that students keep their progress when a course is updated: matching
+/// tests keep their state unless their definition changed, in which case they
+/// need to be run again, tests which are no longer part of the course are
+/// dropped and staggered mode resumes from the same tests. Returns what
+/// changed, or `None` if no tests were stored before.
+///
+/// The update is made in a single transaction, so that an interrupted update
+/// does not leave tests of both versions of the course in db.
 pub fn db_update(
     tree: &sled::Tree,
     tests: &IndexMap<String, TestState>,
-    metadata: CourseMetaData,
-) -> Result<(), DbError> {
-    tree.insert(KEY_METADATA, CourseMetaData::encode(&metadata)).map_err(
-        |err| DbError::DbInsert(hex::encode(KEY_METADATA), err.to_string()),
-    )?;
-
-    // Inserts all new tests. This could be optimized so that only test that
-    // have changed are updated -and this was the case initially. However, the
-    // maintenance cost of deciding when a test in db is invalid proved to be
-    // too much for something as simple (and most likely infrequent) as this
+    metadata: Option<CourseMetaData>,
+    hash: &str,
+) -> Result<Option<CourseUpdate>, DbError> {
+    tree.transaction(|tx| db_update_tx(tx, tests, metadata.as_ref(), hash))
+        .map_err(|err| {
+            let reason = match err {
+                TransactionError::Abort(reason) => reason,
+                TransactionError::Storage(err) => err.to_string(),
+            };
+            DbError::DbUpdate(reason)
+        })
+}
+
+fn db_update_tx(
+    tx: &TransactionalTree,
+    tests: &IndexMap<String, TestState>,
+    metadata: Option<&CourseMetaData>,
+    hash: &str,
+) -> ConflictableTransactionResult<Option<CourseUpdate>, String> {
+    // Course metadata is only available when running against the backend, in
+    // offline mode we keep whatever was stored previously
+    if let Some(metadata) = metadata {
+        tx.insert(KEY_METADATA, metadata.encode())?;
+    }
+
+    let keys_old = match tx.get(KEY_TESTS)? {
+        Some(bytes) => {
+            <Vec<Vec<u8>>>::decode(&mut &bytes[..]).unwrap_or_default()
+        }
+        None => vec![],
+    };
+    let staggered_old = match tx.get(KEY_STAGGERED)? {
+        Some(bytes) => u32::decode(&mut &bytes[..]).unwrap_or(1),
+        None => 1,
+    };
+
+    // Tests stored before the course hash was recorded were migrated from the
+    // first version of the database, which only stored part of the definition
+    // of a test
+    let legacy = tx.get(KEY_HASH)?.is_none();
+
+    // Tests stored in an older format cannot be matched and are dropped. Slugs
+    // are only unique within a lesson.
+    let mut tests_old = IndexMap::new();
+    for key in keys_old.iter() {
+        let Some(bytes) = tx.get(key)? else {
+            continue;
+        };
+        if let Ok(test) = TestState::decode(&mut &bytes[..]) {
+            tests_old
+                .insert((test.lesson_slug.clone(), test.slug.clone()), test);
+        }
+    }
+
+    let mut update = CourseUpdate::default();
+    let mut stage_unlocked = None;
+
     for (key, test) in tests.iter() {
-        tree.insert(key, test.encode()).map_err(|err| {
-            DbError::DbInsert(hex::encode(key), err.to_string())
-        })?;
+        let mut test = test.clone();
+
+        match tests_old
+            .shift_remove(&(test.lesson_slug.clone(), test.slug.clone()))
+        {
+            Some(test_old) => {
+                if test_old.stage < staggered_old {
+                    stage_unlocked = stage_unlocked.max(Some(test.stage));
+                }
+                // A changed test has not been run in its current form
+                if test_changed(&test_old, &test, legacy) {
+                    update.changed += 1;
+                } else {
+                    test.passed = test_old.passed;
+                }
+            }
+            None => update.added += 1,
+        }
+
+        tx.insert(key.as_bytes(), test.encode())?;
+    }
+    update.removed = tests_old.len();
+
+    // Drops tests which are no longer part of the course
+    for key in keys_old.iter() {
+        if !tests.contains_key(String::from_utf8_lossy(key).as_ref()) {
+            tx.remove(key.as_slice())?;
+        }
     }
 
     // Updates the list of available tests
     let test_keys_new =
         tests.into_iter().map(|(key, _)| key).collect::<Vec<_>>();
-    tree.insert(KEY_TESTS, test_keys_new.encode()).map_err(|err| {
-        DbError::DbInsert(hex::encode(KEY_TESTS), err.to_string())
-    })?;
+    tx.insert(KEY_TESTS, test_keys_new.encode())?;
 
-    // Resets staggered test count: this is the number of tests to have
-    // successfully be run sequentially
-    tree.insert(KEY_STAGGERED, 1u32.encode()).map_err(|err| {
-        DbError::DbInsert(hex::encode(KEY_STAGGERED), err.to_string())
-    })?;
+    // Staggered mode resumes with every test which was already unlocked,
+    // wherever it is in the updated course
+    let staggered = stage_unlocked.map_or(1, |stage| stage + 1);
+    tx.insert(KEY_STAGGERED, staggered.encode())?;
+
+    tx.insert(KEY_HASH, hash.to_string().encode())?;
+
+    Ok((!keys_old.is_empty()).then_some(update))
+}
+
+/// Whether the definition of a test has changed in a way which can affect its
+/// result. Names, messages, sections and languages are not compared, so that
+/// fixing a typo does not reset the progress of students, nor are dependencies
+/// and stages as they change along with the tests around them.
+///
+/// If `legacy` is set, `test_old` was migrated from the first version of the
+/// database and only the fields stored at the time are compared.
+fn test_changed(
+    test_old: &TestState,
+    test_new: &TestState,
+    legacy: bool,
+) -> bool {
+    let normalize = |test: &TestState| {
+        let test = TestState {
+            name: String::default(),
+            message_on_success: String::default(),
+            message_on_fail: String::default(),
+            path: vec![],
+            passed: ValidationState::Unknown,
+            section_slug: String::default(),
+            language: None,
+            depends_on: vec![],
+            stage: 0,
+            ..test.clone()
+        };
+
+        match legacy {
+            true => TestState {
+                cmd_error: None,
+                limits: TestLimits::default(),
+                independent: false,
+                env: vec![],
+                cwd: None,
+                stdin: None,
+                expected_stdout: None,
+                expected_stderr: None,
+                ..test
+            },
+            false => test,
+        }
+    };
+
+    normalize(test_old).encode() != normalize(test_new).encode()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::TesterDefinition;
+
+    /// Course with lessons `l1` and `l2`, where each test is given as
+    /// `(lesson, slug, cmd, message_on_success)`.
+    fn course(
+        tests: &[(&str, &str, &str, &str)],
+    ) -> IndexMap<String, TestState> {
+        let lesson = |lesson: &str| {
+            let tests = tests
+                .iter()
+                .filter(|(l, ..)| *l == lesson)
+                .map(|(_, slug, cmd, message)| {
+                    format!(
+                        "          - {{name: {slug}, slug: {slug}, optional: \
+                         false, cmd: '{cmd}', message_on_fail: ko, \
+                         message_on_success: {message}}}\n"
+                    )
+                })
+                .collect::<String>();
+
+            format!(
+                "      - name: {lesson}\n        slug: {lesson}\n        \
+                 duration: 1\n        tests:\n{tests}"
+            )
+        };
+
+        let course = format!(
+            "course_name: Course\nsections:\n  - name: Section\n    slug: \
+             section\n    lessons:\n{}{}",
+            lesson("l1"),
+            lesson("l2"),
+        );
+
+        serde_yaml::from_str::<TesterDefinition>(&course)
+            .unwrap()
+            .list_tests()
+            .unwrap()
+    }
+
+    fn stored(tree: &sled::Tree) -> IndexMap<String, TestState> {
+        let keys = tree.get(KEY_TESTS).unwrap().unwrap();
+        <Vec<Vec<u8>>>::decode(&mut &keys[..])
+            .unwrap()
+            .into_iter()
+            .map(|key| {
+                let bytes = tree.get(&key).unwrap().unwrap();
+                let test = TestState::decode(&mut &bytes[..]).unwrap();
+                (test.id(), test)
+            })
+            .collect()
+    }
 
-    Ok(())
+    fn staggered(tree: &sled::Tree) -> u32 {
+        let bytes = tree.get(KEY_STAGGERED).unwrap().unwrap();
+        u32::decode(&mut &bytes[..]).unwrap()
+    }
+
+    #[test]
+    fn reconciles_tests_with_the_updated_course() {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let tree = db.open_tree(".").unwrap();
+
+        let tests_old = course(&[
+            ("l1", "a", "true", "ok"),
+            ("l1", "b", "true", "ok"),
+            ("l1", "c", "true", "ok"),
+            ("l2", "a", "true", "ok"),
+        ]);
+        assert!(db_update(&tree, &tests_old, None, "old").unwrap().is_none());
+        assert_eq!(staggered(&tree), 1);
+
+        // The student passed every test but 'l1/c', and unlocked every test up
+        // to 'l1/c' in staggered mode
+        for (key, test) in tests_old.iter() {
+            let passed = match test.id().as_str() {
+                "l1/c" => ValidationState::Fail,
+                _ => ValidationState::Pass,
+            };
+            let test = TestState { passed, ..test.clone() };
+            tree.insert(key.as_bytes(), test.encode()).unwrap();
+        }
+        tree.insert(KEY_STAGGERED, 3u32.encode()).unwrap();
+
+        // 'l1/a' only has a new message, 'l1/b' runs a new command, 'l1/c' is
+        // replaced by 'l1/d'
+        let tests_new = course(&[
+            ("l1", "a", "true", "well done"),
+            ("l1", "b", "false", "ok"),
+            ("l1", "d", "true", "ok"),
+            ("l2", "a", "true", "ok"),
+        ]);
+        let update =
+            db_update(&tree, &tests_new, None, "new").unwrap().unwrap();
+        assert_eq!((update.added, update.changed, update.removed), (1, 1, 1));
+
+        let tests = stored(&tree);
+        assert_eq!(
+            tests.keys().collect::<Vec<_>>(),
+            ["l1/a", "l1/b", "l1/d", "l2/a"]
+        );
+        assert!(matches!(tests["l1/a"].passed, ValidationState::Pass));
+        assert_eq!(tests["l1/a"].message_on_success, "well done");
+        assert!(matches!(tests["l1/b"].passed, ValidationState::Unknown));
+        assert!(matches!(tests["l1/d"].passed, ValidationState::Unknown));
+        assert!(matches!(tests["l2/a"].passed, ValidationState::Pass));
+        assert_eq!(tree.len(), tests.len() + 3);
+
+        // 'l1/a' and 'l1/b' were unlocked and still are
+        assert_eq!(staggered(&tree), 2);
+        assert!(!db_should_update(&tree, "new").unwrap());
+    }
+
+    #[test]
+    fn legacy_tests_keep_their_progress() {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let tree = db.open_tree(".").unwrap();
+
+        let tests = course(&[("l1", "a", "true", "ok")]);
+        db_update(&tree, &tests, None, "old").unwrap();
+
+        // Tests migrated from the first version of the database did not store
+        // limits, nor was the course hash recorded
+        for (key, test) in tests.iter() {
+            let test = TestState {
+                passed: ValidationState::Pass,
+                limits: TestLimits { timeout: Some(1), ..Default::default() },
+                ..test.clone()
+            };
+            tree.insert(key.as_bytes(), test.encode()).unwrap();
+        }
+        tree.remove(KEY_HASH).unwrap();
+
+        let update = db_update(&tree, &tests, None, "new").unwrap().unwrap();
+        assert!(update.is_empty());
+        assert!(matches!(stored(&tree)["l1/a"].passed, ValidationState::Pass));
+    }
 }
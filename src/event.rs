@@ -0,0 +1,219 @@
+//! Machine-readable output, enabled with `--format json`.
+//!
+//! In json mode, human-readable output is disabled and the CLI instead writes
+//! one [`Event`] per line to `stdout`, as JSON. This allows editors and scripts
+//! to follow the progress of a command without parsing coloured text.
+
+use std::{
+    io::Write,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use clap::ValueEnum;
+use indicatif::ProgressBar;
+use serde::Serialize;
+
+use crate::{
+    db::{TestState, ValidationState},
+    history::{RunRecord, TestRecord},
+    parsing::TestResult,
+    runner::failures::TestFailure,
+};
+
+static JSON: AtomicBool = AtomicBool::new(false);
+
+#[derive(ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// Coloured, human-readable output
+    #[default]
+    Human,
+    /// Newline-delimited JSON events
+    Json,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    /// A test is listed with `test --list`.
+    TestListed { path: String, slug: String, state: ValidationState },
+    /// A test is about to be reported.
+    TestStarted { index: usize, path: String, slug: String },
+    /// A test has completed.
+    TestFinished {
+        index: usize,
+        path: String,
+        slug: String,
+        state: ValidationState,
+        optional: bool,
+        message: String,
+        stdout: String,
+        stderr: String,
+        duration_ms: u128,
+        /// Differences with the expected output, as plain text.
+        #[serde(skip_serializing_if = "Vec::is_empty")]
+        mismatches: Vec<String>,
+        /// Individual failing tests reported by the test harness.
+        #[serde(skip_serializing_if = "Vec::is_empty")]
+        failures: Vec<TestFailure>,
+    },
+    /// A test was not run as one of its dependencies failed.
+    TestBlocked { index: usize, path: String, slug: String, blocked_by: String },
+    /// Tests were updated along with the course, students keep the state of
+    /// tests which are still part of it and did not change.
+    CourseUpdated { added: usize, changed: usize, removed: usize },
+    /// A test run has completed. `reason` is set if the run failed.
+    Summary {
+        success: bool,
+        passed: u32,
+        total: usize,
+        score: f64,
+        reason: Option<String>,
+    },
+    /// A run recorded in the history, displayed with `history`. `tests` is
+    /// empty when listing runs, and only holds the requested test with
+    /// `history --test`.
+    HistoryRun {
+        id: u64,
+        timestamp: i64,
+        commit: Option<String>,
+        success: bool,
+        passed: usize,
+        total: usize,
+        #[serde(skip_serializing_if = "Vec::is_empty")]
+        tests: Vec<TestRecord>,
+    },
+    /// Queued test results were sent with `sync`.
+    SyncFinished { queued: usize, sent: usize },
+    /// The results of `tests` were forgotten with `db reset`.
+    DbReset { tests: usize },
+    /// Staggered mode was moved to `stage` with `db set-stage`.
+    DbStageSet { stage: u32 },
+    /// The database was written to `path` with `db export`.
+    DbExported { path: String, tests: usize },
+    /// The database was replaced with the export at `path` with `db import`.
+    DbImported { path: String, tests: usize },
+    /// The database was checked with `db verify`.
+    DbVerified { valid: bool, tests: usize, problems: Vec<String> },
+    /// A course element failed validation with `check`.
+    #[cfg(not(debug_assertions))]
+    ValidationError { path: String, slug: String, message: String },
+    /// A course element passed validation with `check`, but might not behave
+    /// the way its author intended.
+    #[cfg(not(debug_assertions))]
+    ValidationWarning { path: String, slug: String, message: String },
+    /// Validation with `check` has completed.
+    #[cfg(not(debug_assertions))]
+    ValidationFinished { valid: bool },
+    /// A command failed before it could complete.
+    Error { message: String },
+}
+
+impl Event {
+    pub fn test_listed(test: &TestState) -> Self {
+        Event::TestListed {
+            path: test.path_to(),
+            slug: test.slug.clone(),
+            state: test.passed.clone(),
+        }
+    }
+
+    pub fn test_started(index: usize, test: &TestState) -> Self {
+        Event::TestStarted {
+            index,
+            path: test.path_to(),
+            slug: test.slug.clone(),
+        }
+    }
+
+    pub fn test_finished(
+        index: usize,
+        test: &TestState,
+        result: &TestResult,
+    ) -> Self {
+        let (state, message) = match result {
+            TestResult::Pass(_) => {
+                (ValidationState::Pass, test.message_on_success.clone())
+            }
+            TestResult::Fail(_) => {
+                (ValidationState::Fail, test.message_on_fail.clone())
+            }
+            TestResult::Timeout(..) => {
+                (ValidationState::Timeout, test.message_on_fail.clone())
+            }
+        };
+        let output = result.output();
+
+        Event::TestFinished {
+            index,
+            path: test.path_to(),
+            slug: test.slug.clone(),
+            state,
+            optional: test.optional,
+            message,
+            stdout: output.stdout.clone(),
+            stderr: output.stderr.clone(),
+            duration_ms: output.duration.as_millis(),
+            mismatches: output
+                .mismatches
+                .iter()
+                .map(|mismatch| {
+                    let mut lines = vec![mismatch.title()];
+                    lines.extend(mismatch.lines(false));
+                    lines.join("\n")
+                })
+                .collect(),
+            failures: output.failures.clone(),
+        }
+    }
+
+    pub fn history_run(
+        id: u64,
+        run: &RunRecord,
+        tests: Vec<TestRecord>,
+    ) -> Self {
+        Event::HistoryRun {
+            id,
+            timestamp: run.timestamp,
+            commit: run.commit.clone(),
+            success: run.success,
+            passed: run.passed(),
+            total: run.tests.len(),
+            tests,
+        }
+    }
+}
+
+/// Sets the output format for the rest of the process.
+pub fn init(format: OutputFormat) {
+    JSON.store(format == OutputFormat::Json, Ordering::Relaxed);
+}
+
+pub fn is_json() -> bool {
+    JSON.load(Ordering::Relaxed)
+}
+
+/// Writes `event` to `stdout` in json mode, does nothing otherwise.
+pub fn emit(event: Event) {
+    if !is_json() {
+        return;
+    }
+
+    match serde_json::to_string(&event) {
+        Ok(json) => {
+            let mut stdout = std::io::stdout().lock();
+            let _ = writeln!(stdout, "{json}");
+            let _ = stdout.flush();
+        }
+        Err(err) => log::error!("failed to serialize event {event:?}: {err}"),
+    }
+}
+
+/// Progress bar used for human-readable output. It is hidden in json mode so
+/// that nothing but events are written to `stdout`.
+pub fn progress_bar() -> ProgressBar {
+    if is_json() {
+        ProgressBar::hidden()
+    } else {
+        ProgressBar::new(0)
+    }
+}
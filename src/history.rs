@@ -0,0 +1,397 @@
+//! History of test runs, browsed with `dotcodeschool history`.
+//!
+//! Every run is recorded in its own tree of the sled database, keyed by run id
+//! in the order runs happened. A run stores the commit of the student's
+//! repository it was made against and the result of each test. Test outputs
+//! are truncated and only the most recent runs are kept, so that the database
+//! does not grow too large over time.
+
+use chrono::{DateTime, Local};
+use colored::Colorize;
+use parity_scale_codec::{Decode, Encode};
+use serde::Serialize;
+use sled::transaction::TransactionError;
+use thiserror::Error;
+
+use crate::{
+    db::{DbError, TestState, ValidationState, TREE_RUNS},
+    event::{self, Event},
+    parsing::TestResult,
+};
+
+/// Maximum number of bytes of `stdout` and `stderr` kept for each test.
+const OUTPUT_MAX: usize = 4096;
+/// Number of characters of a commit hash which are displayed.
+const COMMIT_LEN: usize = 7;
+/// Number of runs kept in the history, older runs are dropped.
+const RUNS_MAX: u64 = 100;
+
+#[derive(Error, Debug)]
+pub enum HistoryError {
+    #[error("{0}")]
+    DbError(#[from] DbError),
+    #[error("no run with id {0}")]
+    RunNotFound(u64),
+    #[error("test '{0}' has not been run yet")]
+    TestNotFound(String),
+}
+
+/// A single test run.
+#[derive(Encode, Decode, Debug, Clone)]
+pub struct RunRecord {
+    /// Start of the run, in seconds since the unix epoch.
+    pub timestamp: i64,
+    /// Commit of the student's repository, if it could be determined.
+    pub commit: Option<String>,
+    pub success: bool,
+    pub tests: Vec<TestRecord>,
+}
+
+/// Result of a test as part of a [`RunRecord`].
+#[derive(Encode, Decode, Serialize, Debug, Clone)]
+pub struct TestRecord {
+    pub slug: String,
+    pub path: String,
+    pub state: ValidationState,
+    pub duration_ms: u64,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+#[derive(Clone)]
+pub struct History {
+    tree: sled::Tree,
+}
+
+impl RunRecord {
+    pub fn new(commit: Option<String>) -> Self {
+        Self {
+            timestamp: chrono::Utc::now().timestamp(),
+            commit,
+            success: false,
+            tests: vec![],
+        }
+    }
+
+    pub fn passed(&self) -> usize {
+        self.tests
+            .iter()
+            .filter(|test| matches!(test.state, ValidationState::Pass))
+            .count()
+    }
+}
+
+impl TestRecord {
+    pub fn new(test: &TestState, result: &TestResult) -> Self {
+        let state = match result {
+            TestResult::Pass(_) => ValidationState::Pass,
+            TestResult::Fail(_) => ValidationState::Fail,
+            TestResult::Timeout(..) => ValidationState::Timeout,
+        };
+        let output = result.output();
+
+        Self {
+            slug: test.slug.clone(),
+            path: test.path_to(),
+            state,
+            duration_ms: output.duration.as_millis() as u64,
+            stdout: output_truncate(&output.stdout),
+            stderr: output_truncate(&output.stderr),
+        }
+    }
+
+    pub fn blocked(test: &TestState) -> Self {
+        Self {
+            slug: test.slug.clone(),
+            path: test.path_to(),
+            state: ValidationState::Blocked,
+            duration_ms: 0,
+            stdout: String::default(),
+            stderr: String::default(),
+        }
+    }
+}
+
+impl History {
+    pub fn open(db: &sled::Db) -> Result<Self, DbError> {
+        let tree = db.open_tree(TREE_RUNS).map_err(|err| {
+            DbError::DbOpenTree(TREE_RUNS.to_string(), err.to_string())
+        })?;
+
+        Ok(Self { tree })
+    }
+
+    /// Records `run`, returning its id. Runs which no longer are among the
+    /// last [`RUNS_MAX`] are dropped along with it.
+    pub fn push(&self, run: &RunRecord) -> Result<u64, DbError> {
+        // Keys are big-endian so that runs are iterated in the order they
+        // happened
+        let id = match self.tree.last() {
+            Ok(Some((key, _))) => {
+                u64::from_be_bytes(key[..].try_into().unwrap_or_default()) + 1
+            }
+            _ => 1,
+        };
+
+        log::debug!("recording run {id}");
+
+        let id_oldest = (id + 1).saturating_sub(RUNS_MAX);
+        let expired = self
+            .tree
+            .range(..id_oldest.to_be_bytes())
+            .keys()
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|err| {
+                DbError::DbGet(TREE_RUNS.to_string(), err.to_string())
+            })?;
+
+        if !expired.is_empty() {
+            log::debug!(
+                "dropping {} runs older than {id_oldest}",
+                expired.len()
+            );
+        }
+
+        self.tree
+            .transaction(|tx| {
+                tx.insert(&id.to_be_bytes(), run.encode())?;
+                for key in expired.iter() {
+                    tx.remove(key)?;
+                }
+
+                Ok(())
+            })
+            .map_err(|err: TransactionError| {
+                DbError::DbInsert(
+                    hex::encode(id.to_be_bytes()),
+                    err.to_string(),
+                )
+            })?;
+
+        Ok(id)
+    }
+
+    pub fn get(&self, id: u64) -> Result<Option<RunRecord>, DbError> {
+        let key = id.to_be_bytes();
+        let query = self
+            .tree
+            .get(key)
+            .map_err(|err| DbError::DbGet(hex::encode(key), err.to_string()))?;
+
+        query
+            .map(|bytes| {
+                RunRecord::decode(&mut &bytes[..]).map_err(|err| {
+                    DbError::DecodeError(hex::encode(key), err.to_string())
+                })
+            })
+            .transpose()
+    }
+
+    /// All recorded runs, from oldest to most recent. Runs which cannot be
+    /// decoded are skipped.
+    pub fn runs(&self) -> Result<Vec<(u64, RunRecord)>, DbError> {
+        let mut runs = vec![];
+
+        for query in self.tree.iter() {
+            let (key, bytes) = query.map_err(|err| {
+                DbError::DbGet(TREE_RUNS.to_string(), err.to_string())
+            })?;
+            let id = u64::from_be_bytes(key[..].try_into().unwrap_or_default());
+
+            match RunRecord::decode(&mut &bytes[..]) {
+                Ok(run) => runs.push((id, run)),
+                Err(err) => log::debug!("skipping run {id}: {err}"),
+            }
+        }
+
+        Ok(runs)
+    }
+}
+
+/// Commit currently checked out in the student's repository.
+pub fn commit_head() -> Option<String> {
+    let repo = git2::Repository::discover(".").ok()?;
+    let commit = repo.head().ok()?.peel_to_commit().ok()?;
+
+    Some(commit.id().to_string())
+}
+
+/// Displays the `limit` most recent runs.
+pub fn runs_print(history: &History, limit: usize) -> Result<(), HistoryError> {
+    let runs = history.runs()?;
+
+    if runs.is_empty() {
+        if !event::is_json() {
+            println!("📭 No tests have been run yet");
+        }
+        return Ok(());
+    }
+
+    for (id, run) in runs.iter().skip(runs.len().saturating_sub(limit)) {
+        if event::is_json() {
+            event::emit(Event::history_run(*id, run, vec![]));
+            continue;
+        }
+
+        let status = if run.success { "✅" } else { "❌" };
+
+        println!(
+            "{status} {} {} {} {}/{} passed",
+            format!("#{id}").bold(),
+            timestamp_format(run.timestamp).white().dimmed(),
+            commit_format(&run.commit).yellow(),
+            run.passed(),
+            run.tests.len(),
+        );
+    }
+
+    Ok(())
+}
+
+/// Displays the result and output of each test in run `id`.
+pub fn run_print(history: &History, id: u64) -> Result<(), HistoryError> {
+    let run = history.get(id)?.ok_or(HistoryError::RunNotFound(id))?;
+
+    if event::is_json() {
+        event::emit(Event::history_run(id, &run, run.tests.clone()));
+        return Ok(());
+    }
+
+    println!(
+        "📒 Run {} on {}, commit {}",
+        format!("#{id}").bold(),
+        timestamp_format(run.timestamp),
+        commit_format(&run.commit).yellow(),
+    );
+
+    for test in run.tests.iter() {
+        println!(
+            "\n{} {} {}",
+            state_format(&test.state),
+            test.path.bold(),
+            format!("({}ms)", test.duration_ms).white().dimmed()
+        );
+
+        for line in test.stdout.lines().chain(test.stderr.lines()) {
+            println!("    │ {line}");
+        }
+    }
+
+    Ok(())
+}
+
+/// Displays the result of test `slug` in each run, and when it first passed.
+pub fn test_print(history: &History, slug: &str) -> Result<(), HistoryError> {
+    let runs = history
+        .runs()?
+        .into_iter()
+        .filter_map(|(id, run)| {
+            let test = run.tests.iter().find(|test| test.slug == slug)?.clone();
+            Some((id, run, test))
+        })
+        .collect::<Vec<_>>();
+
+    if runs.is_empty() {
+        return Err(HistoryError::TestNotFound(slug.to_string()));
+    }
+
+    if event::is_json() {
+        for (id, run, test) in runs.into_iter() {
+            event::emit(Event::history_run(id, &run, vec![test]));
+        }
+        return Ok(());
+    }
+
+    let passed_first = runs
+        .iter()
+        .find(|(_, _, test)| matches!(test.state, ValidationState::Pass));
+
+    match passed_first {
+        Some((id, run, test)) => println!(
+            "🏆 {} first passed in run {} on {}, commit {}\n",
+            test.path.bold(),
+            format!("#{id}").bold(),
+            timestamp_format(run.timestamp),
+            commit_format(&run.commit).yellow(),
+        ),
+        None => println!("🚧 {slug} has not passed yet\n"),
+    }
+
+    for (id, run, test) in runs.iter() {
+        println!(
+            "{} {} {} {} {}",
+            state_format(&test.state),
+            format!("#{id}").bold(),
+            timestamp_format(run.timestamp).white().dimmed(),
+            commit_format(&run.commit).yellow(),
+            format!("({}ms)", test.duration_ms).white().dimmed()
+        );
+    }
+
+    Ok(())
+}
+
+fn output_truncate(output: &str) -> String {
+    if output.len() <= OUTPUT_MAX {
+        return output.to_string();
+    }
+
+    let end = (0..=OUTPUT_MAX)
+        .rev()
+        .find(|index| output.is_char_boundary(*index))
+        .unwrap_or_default();
+
+    format!("{}\n… (truncated)", &output[..end])
+}
+
+fn timestamp_format(timestamp: i64) -> String {
+    match DateTime::from_timestamp(timestamp, 0) {
+        Some(time) => {
+            time.with_timezone(&Local).format("%Y-%m-%d %H:%M:%S").to_string()
+        }
+        None => String::default(),
+    }
+}
+
+fn commit_format(commit: &Option<String>) -> String {
+    match commit {
+        Some(commit) => commit.chars().take(COMMIT_LEN).collect(),
+        None => "-------".to_string(),
+    }
+}
+
+fn state_format(state: &ValidationState) -> String {
+    match state {
+        ValidationState::Pass => "✅".to_string(),
+        ValidationState::Fail => "❌".to_string(),
+        ValidationState::Timeout => "⌛".to_string(),
+        ValidationState::Blocked => "⛔".to_string(),
+        ValidationState::Unknown => "❔".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use itertools::Itertools;
+
+    use super::*;
+
+    #[test]
+    fn keeps_the_last_runs() {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let history = History::open(&db).unwrap();
+
+        for _ in 0..RUNS_MAX + 2 {
+            history.push(&RunRecord::new(None)).unwrap();
+        }
+
+        let ids =
+            history.runs().unwrap().into_iter().map(|(id, _)| id).collect_vec();
+        assert_eq!(ids, (3..=RUNS_MAX + 2).collect_vec());
+
+        // Ids keep increasing once older runs are dropped
+        assert_eq!(history.push(&RunRecord::new(None)).unwrap(), RUNS_MAX + 3);
+        assert_eq!(history.runs().unwrap().len() as u64, RUNS_MAX);
+        assert!(history.get(3).unwrap().is_none());
+    }
+}
@@ -5,6 +5,7 @@ use parity_scale_codec::Decode;
 
 use crate::{
     db::{TestState, ValidationState},
+    event::{self, Event},
     monitor::StateMachine,
 };
 
@@ -72,6 +73,8 @@ impl StateMachine for ListerV1 {
                         let path_to = test.path_to().to_lowercase();
                         let test_name = test.name.to_lowercase();
 
+                        event::emit(Event::test_listed(&test));
+
                         match test.passed {
                             ValidationState::Unknown => {
                                 progress.println(format!(
@@ -93,6 +96,22 @@ impl StateMachine for ListerV1 {
                                 path_to.white().dimmed().italic(),
                                 test_name.white().bold(),
                             )),
+                            ValidationState::Timeout => {
+                                progress.println(format!(
+                                    "• {} {}/{}",
+                                    "[Time out]".red().bold(),
+                                    path_to.white().dimmed().italic(),
+                                    test_name.white().bold(),
+                                ))
+                            }
+                            ValidationState::Blocked => {
+                                progress.println(format!(
+                                    "• {} {}/{}",
+                                    "[Blocked ]".yellow().bold(),
+                                    path_to.white().dimmed().italic(),
+                                    test_name.white().bold(),
+                                ))
+                            }
                         }
 
                         if index_test + 1 < tests.len() {
@@ -137,6 +156,7 @@ impl StateMachine for ListerV1 {
             ListerStateV1::Error { reason } => {
                 progress.finish_and_clear();
                 progress.println(format!("\n⚠ Error: {}", reason.red().bold()));
+                event::emit(Event::Error { message: reason.clone() });
 
                 Self {
                     progress,
@@ -0,0 +1,137 @@
+//! Advisory lock preventing concurrent runs in the same repository.
+//!
+//! Only one process at a time may open the database, so a second run, for
+//! example from an editor plugin while tests are running in a terminal, waits
+//! for or is refused the lock instead of failing to open the database. The
+//! lock file holds the pid of the process owning the lock, and the lock is
+//! released by the operating system when that process exits, so it is never
+//! left stale.
+
+use std::{
+    fs::File,
+    io::{Read, Seek, Write},
+    os::fd::AsRawFd,
+    path::Path,
+};
+
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum LockError {
+    #[error("failed to open lock file at '{0}': {1}")]
+    Open(String, String),
+    #[error("failed to acquire lock at '{0}': {1}")]
+    Acquire(String, String),
+    #[error(
+        "another run is in progress (pid {0}), use --wait to wait for it to \
+         finish"
+    )]
+    Held(String),
+}
+
+/// Lock held for as long as this value is alive.
+#[derive(Debug)]
+pub struct RunLock {
+    _file: File,
+}
+
+impl RunLock {
+    /// Acquires the lock at `path`. If another process holds it, either waits
+    /// for it to be released or fails, depending on `wait`.
+    pub fn acquire(path: &Path, wait: bool) -> Result<Self, LockError> {
+        let err_acquire = |err: std::io::Error| {
+            LockError::Acquire(path.display().to_string(), err.to_string())
+        };
+
+        let mut file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)
+            .map_err(|err| {
+                LockError::Open(path.display().to_string(), err.to_string())
+            })?;
+
+        if !flock(&file, false).map_err(err_acquire)? {
+            let pid = Self::pid_read(&mut file);
+
+            if !wait {
+                return Err(LockError::Held(pid));
+            }
+
+            log::debug!("waiting for run with pid {pid} to finish");
+            eprintln!("⏳ Waiting for another run to finish (pid {pid})");
+
+            flock(&file, true).map_err(err_acquire)?;
+        }
+
+        log::debug!("acquired lock at '{}'", path.display());
+
+        // The previous owner might have written a longer pid
+        file.set_len(0)
+            .and_then(|_| file.rewind())
+            .and_then(|_| write!(file, "{}", std::process::id()))
+            .map_err(err_acquire)?;
+
+        Ok(Self { _file: file })
+    }
+
+    fn pid_read(file: &mut File) -> String {
+        let mut pid = String::default();
+
+        match file.read_to_string(&mut pid) {
+            Ok(_) if !pid.trim().is_empty() => pid.trim().to_string(),
+            _ => "unknown".to_string(),
+        }
+    }
+}
+
+/// Places an exclusive lock on `file`, returning whether it was acquired. If
+/// `block` is set, waits until the lock is available instead.
+fn flock(file: &File, block: bool) -> Result<bool, std::io::Error> {
+    let operation = match block {
+        true => libc::LOCK_EX,
+        false => libc::LOCK_EX | libc::LOCK_NB,
+    };
+
+    loop {
+        // SAFETY: the file descriptor is valid for as long as `file` is.
+        if unsafe { libc::flock(file.as_raw_fd(), operation) } == 0 {
+            return Ok(true);
+        }
+
+        let err = std::io::Error::last_os_error();
+        match err.kind() {
+            std::io::ErrorKind::WouldBlock => return Ok(false),
+            std::io::ErrorKind::Interrupted => continue,
+            _ => return Err(err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn refuses_held_locks() {
+        let path = std::env::temp_dir()
+            .join(format!("dcs-lock-{}.lock", std::process::id()));
+
+        let lock = RunLock::acquire(&path, false).unwrap();
+
+        // Locks are held by open files, not processes
+        match RunLock::acquire(&path, false) {
+            Err(LockError::Held(pid)) => {
+                assert_eq!(pid, std::process::id().to_string())
+            }
+            result => panic!("expected the lock to be held, got {result:?}"),
+        }
+
+        drop(lock);
+        assert!(RunLock::acquire(&path, false).is_ok());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,16 +1,35 @@
 use clap::{Args, Parser, Subcommand};
-use constants::LOG;
-use db::PATH_DB;
-use monitor::{Monitor, MonitorError, StateMachine};
+use colored::Colorize;
+use config::Config;
+use constants::PATH_TESTER_DEFINITION;
+use db::{db_open, db_open_raw};
+use event::{Event, OutputFormat};
+use history::History;
+use lock::RunLock;
+use monitor::{Monitor, MonitorError, RunOptions, StateMachine, TestSelection};
+use report::ReportFormat;
+use reporter::outbox::Outbox;
+use storage::Storage;
 
+mod config;
 mod constants;
 mod db;
+mod event;
+mod history;
 mod lister;
+mod lock;
+mod maintenance;
+mod migration;
 mod models;
 mod monitor;
 mod parsing;
+mod report;
+mod reporter;
 mod runner;
+mod selection;
+mod storage;
 mod str_res;
+#[cfg(not(debug_assertions))]
 mod validator;
 
 #[derive(Parser, Debug)]
@@ -18,8 +37,30 @@ mod validator;
 struct Cli {
     #[command(subcommand)]
     command: Command,
+    /// Path to the database, stored in the user's data directory by default
     #[arg(long)]
     db: Option<String>,
+    /// Run without contacting the DotCodeSchool backend, using a local
+    /// tester-definition.yml
+    #[arg(long, global = true)]
+    offline: bool,
+    /// Path to a local tester definition, implies --offline
+    #[arg(long, global = true)]
+    tester_definition: Option<String>,
+    /// Base URL of the DotCodeSchool backend, overrides DCS_BACKEND_URL and
+    /// config files
+    #[arg(long, global = true)]
+    backend_url: Option<String>,
+    /// Display additional information such as the effective configuration
+    #[arg(long, short, global = true)]
+    verbose: bool,
+    /// Wait for other runs in the same repository to finish instead of
+    /// exiting
+    #[arg(long, global = true)]
+    wait: bool,
+    /// Output format, 'json' writes newline-delimited events to stdout
+    #[arg(long, global = true, value_enum, default_value_t)]
+    format: OutputFormat,
 }
 
 #[derive(Subcommand, Debug)]
@@ -31,6 +72,18 @@ enum Command {
     /// empty commit and submit it
     #[command(name = "submit")]
     Submit(SubmitArgs),
+    /// Send test results which could not be delivered to DotCodeSchool
+    #[command(name = "sync")]
+    Sync,
+    /// Browse the results of past test runs
+    #[command(name = "history")]
+    History(HistoryArgs),
+    /// Inspect and repair the local database
+    #[command(name = "db", subcommand)]
+    Db(DbCommand),
+    /// Reload the course and its tests, even if they have not changed
+    #[command(name = "refresh")]
+    Refresh,
     #[cfg(not(debug_assertions))]
     #[command(name = "check")]
     Check,
@@ -39,24 +92,98 @@ enum Command {
 #[derive(Args, Debug)]
 #[group(required = false, multiple = false)]
 struct TestArgs {
-    #[arg(group = "exclusive")]
+    /// Test to run, as 'section/lesson/test', a section, a lesson or a test
+    /// slug. Parts can be names or slugs, and can contain '*' wildcards
+    #[arg(group = "exclusive", conflicts_with = "selection")]
     name: Option<String>,
+    /// Additional tests to run, in the same format as the test name. Can be
+    /// repeated
+    #[arg(long = "test", value_name = "PATTERN", conflicts_with = "selection")]
+    tests: Vec<String>,
     #[command(flatten)]
     options: TestOptions,
+    /// Number of tests marked as independent to run concurrently
+    #[arg(long, short, default_value_t = 1)]
+    jobs: usize,
+    /// Write a test report: 'junit=<path>', 'tap' (to stdout) or 'tap=<path>'.
+    /// Can be repeated
+    #[arg(long, value_name = "FORMAT")]
+    report: Vec<ReportFormat>,
+    /// Run tests without network access and with a read-only filesystem
+    /// outside of the test environment (Linux only)
+    #[arg(long)]
+    sandbox: bool,
 }
 
+/// Tests are selected by name, or with at most one of the flags in the
+/// "selection" group, and are otherwise run in staggered mode.
 #[derive(Args, Debug)]
-#[group(required = false, multiple = false)]
 struct TestOptions {
     /// List all available tests for the course
-    #[arg(long, group = "exclusive")]
+    #[arg(
+        long,
+        group = "exclusive",
+        conflicts_with_all = ["tests", "selection", "keep"]
+    )]
     list: bool,
     /// Run all tests at once
-    #[arg(long)]
+    #[arg(long, group = "selection")]
     all: bool,
+    /// Run only the tests which failed the last time they were run
+    #[arg(long, group = "selection")]
+    failed: bool,
+    /// Run only the tests which have not been run yet
+    #[arg(long, group = "selection")]
+    unknown: bool,
     /// Do not destroy the test environment after running the tests
     #[arg(long)]
     keep: bool,
+    /// Re-run tests in staggered mode each time a file is saved. The test
+    /// environment is destroyed when exiting
+    #[arg(long, group = "selection", conflicts_with = "keep")]
+    watch: bool,
+}
+
+#[derive(Args, Debug)]
+struct HistoryArgs {
+    /// Id of a run to display the result and output of each test for
+    run: Option<u64>,
+    /// Display the result of a test in each run, by slug, and when it first
+    /// passed
+    #[arg(long, conflicts_with = "run")]
+    test: Option<String>,
+    /// Number of most recent runs to list
+    #[arg(long, short = 'n', default_value_t = 20)]
+    limit: usize,
+}
+
+#[derive(Subcommand, Debug)]
+enum DbCommand {
+    /// Display the course metadata, staggered progress and every stored test
+    #[command(name = "show")]
+    Show,
+    /// Forget the results of all tests, or of the tests matching the given
+    /// patterns. Resetting all tests also resets staggered mode
+    #[command(name = "reset")]
+    Reset {
+        /// Tests to reset, in the same format as 'test'
+        patterns: Vec<String>,
+    },
+    /// Run every test before stage N in staggered mode
+    #[command(name = "set-stage")]
+    SetStage {
+        #[arg(value_parser = clap::value_parser!(u32).range(1..))]
+        stage: u32,
+    },
+    /// Write the database as JSON, to stdout if no path is given
+    #[command(name = "export")]
+    Export { path: Option<String> },
+    /// Replace the database with a JSON export
+    #[command(name = "import")]
+    Import { path: String },
+    /// Check that every stored test exists and can be decoded
+    #[command(name = "verify")]
+    Verify,
 }
 
 #[derive(Args, Debug)]
@@ -69,11 +196,36 @@ struct SubmitArgs {
 fn main() -> Result<(), MonitorError> {
     let args = Cli::parse();
 
+    event::init(args.format);
+
+    let path_tester = match (&args.tester_definition, args.offline) {
+        (Some(path), _) => Some(path.clone()),
+        (None, true) => Some(PATH_TESTER_DEFINITION.to_string()),
+        (None, false) => None,
+    };
+
+    let storage = Storage::locate(args.db.as_deref(), path_tester.as_deref());
+    if let Err(err) = storage.init() {
+        exit_error(MonitorError::from(err));
+    }
+
+    // Held until the end of the run, so that concurrent runs in the same
+    // repository do not share the database or the tester workspace, and taken
+    // before moving the state left by earlier versions of the CLI
+    let _lock = match RunLock::acquire(&storage.path_lock(), args.wait) {
+        Ok(lock) => lock,
+        Err(err) => exit_error(MonitorError::from(err)),
+    };
+
+    if let Err(err) = storage.migrate() {
+        exit_error(MonitorError::from(err));
+    }
+
     let file = std::fs::OpenOptions::new()
         .read(true)
         .create(true)
         .append(true)
-        .open(LOG)?;
+        .open(storage.path_log())?;
 
     let _ = simplelog::WriteLogger::init(
         simplelog::LevelFilter::Debug,
@@ -83,32 +235,106 @@ fn main() -> Result<(), MonitorError> {
         file,
     );
 
-    let path_db = match args.db {
-        Some(path) => path,
-        None => PATH_DB.to_string(),
-    };
+    // Test processes run in their own process group and must be stopped
+    // explicitly. In watch mode, the tester workspace is cleaned up before
+    // exiting.
+    let watch = matches!(
+        &args.command,
+        Command::Test(TestArgs { options, .. }) if options.watch
+    );
+    let _ = ctrlc::set_handler(move || {
+        runner::process::interrupt();
+        if !watch {
+            std::process::exit(130);
+        }
+    });
+
+    if let Err(err) = run(args, storage, path_tester) {
+        exit_error(err);
+    }
+
+    Ok(())
+}
+
+fn exit_error(err: MonitorError) -> ! {
+    event::emit(Event::Error { message: err.to_string() });
+
+    if !event::is_json() {
+        eprintln!("{} {err}", "Error:".red().bold());
+    }
+    std::process::exit(1);
+}
+
+fn run(
+    args: Cli,
+    storage: Storage,
+    path_tester: Option<String>,
+) -> Result<(), MonitorError> {
+    let path_db = storage.path_db();
+
+    let config = Config::load(args.backend_url)?;
+
+    if args.verbose && !event::is_json() {
+        println!(
+            "{} {} ({})",
+            "backend:".white().dimmed(),
+            config.backend_url().white().bold(),
+            config.backend_url_source()
+        );
+        println!("{} {}", "database:".white().dimmed(), path_db.white().bold());
+    }
+
+    // Syncing does not require the course to be loaded
+    if let Command::Sync = args.command {
+        return handle_sync(&path_db);
+    }
+
+    if let Command::History(history_args) = args.command {
+        return handle_history(&path_db, history_args);
+    }
 
-    let monitor = Monitor::new(&path_db)?;
+    if let Command::Db(db_command) = args.command {
+        return handle_db(&path_db, db_command);
+    }
+
+    let refresh = matches!(args.command, Command::Refresh);
+    let monitor = Monitor::new(&path_db, path_tester, config, refresh)?;
 
     match args.command {
-        Command::Test(TestArgs { name, options }) => {
-            if options.list {
+        Command::Test(TestArgs {
+            name,
+            tests,
+            options,
+            jobs,
+            report,
+            sandbox,
+        }) => {
+            let run_options = RunOptions {
+                keep: options.keep,
+                jobs,
+                reports: report,
+                sandbox,
+            };
+
+            if options.watch {
+                monitor.watch(run_options, &path_db)?;
+            } else if options.list {
                 let mut lister = monitor.into_lister()?;
 
                 while !lister.is_finished() {
                     lister = lister.run();
                 }
-            } else if options.all || name.is_some() {
-                let mut runner = monitor.into_runner(name, options.keep)?;
-
-                while !runner.is_finished() {
-                    runner = runner.run();
-                }
             } else {
-                let mut runner = monitor.into_runner(name, options.keep)?;
-                // TODO: replace with into_runner_staggered
-                // let mut runner =
-                // monitor.into_runner_staggered(options.keep)?;
+                let patterns =
+                    name.into_iter().chain(tests).collect::<Vec<_>>();
+                let selection = match patterns.is_empty() {
+                    false => TestSelection::Patterns(patterns),
+                    true if options.failed => TestSelection::Failed,
+                    true if options.unknown => TestSelection::Unknown,
+                    true if options.all => TestSelection::All,
+                    true => TestSelection::All,
+                };
+                let mut runner = monitor.into_runner(selection, run_options)?;
 
                 while !runner.is_finished() {
                     runner = runner.run();
@@ -118,6 +344,14 @@ fn main() -> Result<(), MonitorError> {
         Command::Submit(SubmitArgs { empty }) => {
             handle_submit(empty)?;
         }
+        Command::Sync | Command::History(_) | Command::Db(_) => {
+            unreachable!("handled before loading the course")
+        }
+        Command::Refresh => {
+            if !event::is_json() {
+                println!("{}", "✅ Course refreshed".green().bold());
+            }
+        }
         #[cfg(not(debug_assertions))]
         Command::Check => {
             let mut validator = monitor.into_validator();
@@ -131,6 +365,75 @@ fn main() -> Result<(), MonitorError> {
     Ok(())
 }
 
+fn handle_sync(path_db: &str) -> Result<(), MonitorError> {
+    let (db, _) = db_open(path_db, ".")?;
+    let outbox = Outbox::open(&db)?;
+
+    let queued = outbox.len();
+    if queued == 0 {
+        if !event::is_json() {
+            println!("✅ No test results left to send");
+        }
+        event::emit(Event::SyncFinished { queued, sent: 0 });
+        return Ok(());
+    }
+
+    if !event::is_json() {
+        println!("📤 Sending {queued} queued events to DotCodeSchool");
+    }
+
+    let sent = outbox.flush()?;
+    if !event::is_json() {
+        println!("{}", format!("✅ Sent {sent} events").green().bold());
+    }
+    event::emit(Event::SyncFinished { queued, sent });
+
+    Ok(())
+}
+
+fn handle_history(
+    path_db: &str,
+    args: HistoryArgs,
+) -> Result<(), MonitorError> {
+    let (db, _) = db_open(path_db, ".")?;
+    let history = History::open(&db)?;
+
+    match (args.run, args.test) {
+        (Some(id), _) => history::run_print(&history, id)?,
+        (None, Some(slug)) => history::test_print(&history, &slug)?,
+        (None, None) => history::runs_print(&history, args.limit)?,
+    }
+
+    Ok(())
+}
+
+fn handle_db(path_db: &str, command: DbCommand) -> Result<(), MonitorError> {
+    // Only commands which modify tests need them in the current layout
+    let (_, tree) = match command {
+        DbCommand::Reset { .. } | DbCommand::SetStage { .. } => {
+            db_open(path_db, ".")?
+        }
+        _ => db_open_raw(path_db, ".")?,
+    };
+
+    match command {
+        DbCommand::Show => maintenance::db_show(&tree)?,
+        DbCommand::Reset { patterns } => {
+            maintenance::db_reset(&tree, &patterns)?
+        }
+        DbCommand::SetStage { stage } => {
+            maintenance::db_set_stage(&tree, stage)?
+        }
+        DbCommand::Export { path } => {
+            maintenance::db_export(&tree, path.as_deref())?
+        }
+        DbCommand::Import { path } => maintenance::db_import(&tree, &path)?,
+        DbCommand::Verify => maintenance::db_verify(&tree)?,
+    }
+
+    Ok(())
+}
+
 fn handle_submit(empty: bool) -> Result<(), MonitorError> {
     if empty {
         // Create an empty commit
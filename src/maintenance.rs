@@ -0,0 +1,433 @@
+//! Inspection and repair of the local database, with `dotcodeschool db`.
+//!
+//! These commands only need the database and work without loading the course,
+//! so that a database which is in a bad state can still be looked at and fixed.
+//! Commands which only read the database, and `db import` which replaces it,
+//! do not migrate it to the current schema version first.
+
+use std::io::Write;
+
+use colored::Colorize;
+use parity_scale_codec::{Decode, Encode};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{
+    db::{
+        DbError, TestState, ValidationState, KEY_HASH, KEY_METADATA,
+        KEY_STAGGERED, KEY_TESTS, KEY_VERSION,
+    },
+    event::{self, Event},
+    migration::{db_version, SCHEMA_VERSION},
+    parsing::CourseMetaData,
+    selection::{tests_select, SelectionError},
+};
+
+#[derive(Error, Debug)]
+pub enum MaintenanceError {
+    #[error("{0}")]
+    DbError(#[from] DbError),
+    #[error("{0}")]
+    SelectionError(#[from] SelectionError),
+    #[error("failed to read '{0}': {1}")]
+    ReadError(String, String),
+    #[error("failed to write '{0}': {1}")]
+    WriteError(String, String),
+    #[error("invalid database export: {0}")]
+    ImportFmt(String),
+    #[error(
+        "database export is at version {0}, this version of dotcodeschool \
+         can only import version {1}"
+    )]
+    ImportVersion(u32, u32),
+    #[error("found {0} problems in the database")]
+    Invalid(usize),
+    #[error(
+        "database is at version {0}, run any test to migrate it to version {1} \
+         first"
+    )]
+    Outdated(u32, u32),
+}
+
+/// Everything stored about the course, as written by `db export`.
+#[derive(Serialize, Deserialize, Debug)]
+struct DbExport {
+    version: u32,
+    hash: Option<String>,
+    staggered: u32,
+    metadata: Option<CourseMetaData>,
+    tests: Vec<TestExport>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct TestExport {
+    key: String,
+    test: TestState,
+}
+
+/// Displays the course metadata, staggered progress and every stored test. In
+/// json mode, writes the same JSON as `db export` instead.
+pub fn db_show(tree: &sled::Tree) -> Result<(), MaintenanceError> {
+    let export = db_read(tree)?;
+
+    if event::is_json() {
+        return json_write(&export, None);
+    }
+
+    println!("🗄  {} {}", "version:".bold(), export.version);
+    println!(
+        "🔑 {} {}",
+        "course hash:".bold(),
+        export.hash.as_deref().unwrap_or("none")
+    );
+    println!(
+        "🪜 {} running tests before stage {}",
+        "staggered:".bold(),
+        export.staggered
+    );
+    match &export.metadata {
+        Some(metadata) => println!("🌐 {} {metadata:#?}", "metadata:".bold()),
+        None => println!("🌐 {} none (offline)", "metadata:".bold()),
+    }
+
+    println!("\n📋 {} tests", export.tests.len().to_string().bold());
+    for TestExport { key, test } in export.tests.iter() {
+        println!(
+            "\n{} {} {}",
+            state_format(&test.passed),
+            test.path_to().bold(),
+            format!("(key '{key}')").white().dimmed()
+        );
+        println!("{test:#?}");
+    }
+
+    Ok(())
+}
+
+/// Forgets the results of the tests matched by `patterns`, or of every test if
+/// no patterns are given. Resetting every test also resets staggered mode.
+pub fn db_reset(
+    tree: &sled::Tree,
+    patterns: &[String],
+) -> Result<(), MaintenanceError> {
+    let tests = tests_load(tree)?;
+    let tests = match patterns.is_empty() {
+        true => tests,
+        false => tests_select(tests, patterns)?,
+    };
+
+    for (key, mut test) in tests.iter().cloned() {
+        test.passed = ValidationState::Unknown;
+        tree.insert(&key, test.encode()).map_err(|err| {
+            DbError::DbInsert(hex::encode(&key), err.to_string())
+        })?;
+    }
+
+    if patterns.is_empty() {
+        db_insert(tree, KEY_STAGGERED, 1u32.encode())?;
+    }
+
+    if !event::is_json() {
+        println!("🔄 Reset {} tests", tests.len().to_string().bold());
+    }
+    event::emit(Event::DbReset { tests: tests.len() });
+
+    Ok(())
+}
+
+/// Runs every test before `stage` in staggered mode.
+pub fn db_set_stage(
+    tree: &sled::Tree,
+    stage: u32,
+) -> Result<(), MaintenanceError> {
+    db_insert(tree, KEY_STAGGERED, stage.encode())?;
+
+    if !event::is_json() {
+        println!(
+            "🪜 Staggered mode now runs tests before stage {}",
+            stage.to_string().bold()
+        );
+    }
+    event::emit(Event::DbStageSet { stage });
+
+    Ok(())
+}
+
+/// Writes the database as JSON to `path`, or to stdout.
+pub fn db_export(
+    tree: &sled::Tree,
+    path: Option<&str>,
+) -> Result<(), MaintenanceError> {
+    let export = db_read(tree)?;
+    json_write(&export, path)?;
+
+    if let Some(path) = path {
+        if !event::is_json() {
+            println!(
+                "📦 Exported {} tests to {path}",
+                export.tests.len().to_string().bold()
+            );
+        }
+        event::emit(Event::DbExported {
+            path: path.to_string(),
+            tests: export.tests.len(),
+        });
+    }
+
+    Ok(())
+}
+
+/// Replaces the content of the database with an export read from `path`.
+pub fn db_import(
+    tree: &sled::Tree,
+    path: &str,
+) -> Result<(), MaintenanceError> {
+    let json = std::fs::read_to_string(path).map_err(|err| {
+        MaintenanceError::ReadError(path.to_string(), err.to_string())
+    })?;
+    let export = serde_json::from_str::<DbExport>(&json)
+        .map_err(|err| MaintenanceError::ImportFmt(err.to_string()))?;
+
+    if export.version != SCHEMA_VERSION {
+        return Err(MaintenanceError::ImportVersion(
+            export.version,
+            SCHEMA_VERSION,
+        ));
+    }
+
+    // Everything is replaced at once, so that a failed import leaves the
+    // database untouched
+    let mut batch = sled::Batch::default();
+    for key in tree.iter().keys().flatten() {
+        batch.remove(key);
+    }
+
+    batch.insert(KEY_VERSION, export.version.encode());
+    batch.insert(KEY_STAGGERED, export.staggered.encode());
+    if let Some(hash) = &export.hash {
+        batch.insert(KEY_HASH, hash.encode());
+    }
+    if let Some(metadata) = &export.metadata {
+        batch.insert(KEY_METADATA, metadata.encode());
+    }
+
+    let keys = export
+        .tests
+        .iter()
+        .map(|TestExport { key, .. }| key.as_bytes().to_vec())
+        .collect::<Vec<_>>();
+    batch.insert(KEY_TESTS, keys.encode());
+    for TestExport { key, test } in export.tests.iter() {
+        batch.insert(key.as_bytes(), test.encode());
+    }
+
+    tree.apply_batch(batch)
+        .map_err(|err| DbError::DbInsert(path.to_string(), err.to_string()))?;
+
+    if !event::is_json() {
+        println!(
+            "📥 Imported {} tests from {path}",
+            export.tests.len().to_string().bold()
+        );
+    }
+    event::emit(Event::DbImported {
+        path: path.to_string(),
+        tests: export.tests.len(),
+    });
+
+    Ok(())
+}
+
+/// Checks that every test listed in the database exists and can be decoded.
+pub fn db_verify(tree: &sled::Tree) -> Result<(), MaintenanceError> {
+    let mut problems = vec![];
+
+    // Tests of older versions are stored in another layout
+    let version = db_version(tree)?.unwrap_or(SCHEMA_VERSION);
+    if version > SCHEMA_VERSION {
+        return Err(DbError::DbVersion(version, SCHEMA_VERSION).into());
+    }
+    if version < SCHEMA_VERSION {
+        problems.push(format!(
+            "the database is at version {version} and needs to be migrated to \
+             version {SCHEMA_VERSION}"
+        ));
+    }
+
+    let keys = match db_get(tree, KEY_TESTS)? {
+        Some(bytes) => match <Vec<Vec<u8>>>::decode(&mut &bytes[..]) {
+            Ok(keys) => keys,
+            Err(err) => {
+                problems.push(format!("the list of tests is invalid: {err}"));
+                vec![]
+            }
+        },
+        None => {
+            problems.push("no tests are listed".to_string());
+            vec![]
+        }
+    };
+
+    for key in keys.iter() {
+        let name = String::from_utf8_lossy(key);
+
+        match db_get(tree, key)? {
+            Some(_) if version < SCHEMA_VERSION => {}
+            Some(bytes) => {
+                if let Err(err) = TestState::decode(&mut &bytes[..]) {
+                    problems.push(format!("test '{name}' is invalid: {err}"));
+                }
+            }
+            None => problems.push(format!("test '{name}' is missing")),
+        }
+    }
+
+    if let Some(bytes) = db_get(tree, KEY_STAGGERED)? {
+        if let Err(err) = u32::decode(&mut &bytes[..]) {
+            problems.push(format!("staggered progress is invalid: {err}"));
+        }
+    }
+
+    if let Some(bytes) = db_get(tree, KEY_METADATA)? {
+        if let Err(err) = CourseMetaData::decode(&mut &bytes[..]) {
+            problems.push(format!("course metadata is invalid: {err}"));
+        }
+    }
+
+    event::emit(Event::DbVerified {
+        valid: problems.is_empty(),
+        tests: keys.len(),
+        problems: problems.clone(),
+    });
+
+    if problems.is_empty() {
+        if !event::is_json() {
+            println!(
+                "{}",
+                format!("✅ Database is valid, with {} tests", keys.len())
+                    .green()
+                    .bold()
+            );
+        }
+        return Ok(());
+    }
+
+    if !event::is_json() {
+        for problem in problems.iter() {
+            println!("❌ {problem}");
+        }
+    }
+
+    Err(MaintenanceError::Invalid(problems.len()))
+}
+
+/// Everything stored in the database, which must be at the current schema
+/// version for tests to be decoded.
+fn db_read(tree: &sled::Tree) -> Result<DbExport, MaintenanceError> {
+    let version = db_version(tree)?.unwrap_or(SCHEMA_VERSION);
+    if version > SCHEMA_VERSION {
+        return Err(DbError::DbVersion(version, SCHEMA_VERSION).into());
+    }
+    if version < SCHEMA_VERSION {
+        return Err(MaintenanceError::Outdated(version, SCHEMA_VERSION));
+    }
+
+    let hash = db_decode::<String>(tree, KEY_HASH)?;
+    let staggered = db_decode::<u32>(tree, KEY_STAGGERED)?.unwrap_or(1);
+    let metadata = db_decode::<CourseMetaData>(tree, KEY_METADATA)?;
+
+    let tests = tests_load(tree)?
+        .into_iter()
+        .map(|(key, test)| TestExport {
+            key: String::from_utf8_lossy(&key).to_string(),
+            test,
+        })
+        .collect();
+
+    Ok(DbExport { version, hash, staggered, metadata, tests })
+}
+
+/// Every test listed in the database, in order.
+fn tests_load(
+    tree: &sled::Tree,
+) -> Result<Vec<(Vec<u8>, TestState)>, MaintenanceError> {
+    let keys = db_decode::<Vec<Vec<u8>>>(tree, KEY_TESTS)?.unwrap_or_default();
+
+    keys.into_iter()
+        .filter_map(|key| match db_decode::<TestState>(tree, &key) {
+            Ok(Some(test)) => Some(Ok((key, test))),
+            Ok(None) => None,
+            Err(err) => Some(Err(err)),
+        })
+        .collect()
+}
+
+/// Writes `export` as JSON to `path`, or to stdout. Exports are indented, and
+/// written on a single line to stdout in json mode.
+fn json_write(
+    export: &DbExport,
+    path: Option<&str>,
+) -> Result<(), MaintenanceError> {
+    let err_write = |err: String| {
+        MaintenanceError::WriteError(path.unwrap_or("stdout").to_string(), err)
+    };
+
+    let json = match path.is_none() && event::is_json() {
+        true => serde_json::to_string(export),
+        false => serde_json::to_string_pretty(export),
+    }
+    .map_err(|err| err_write(err.to_string()))?;
+
+    match path {
+        Some(path) => std::fs::write(path, json),
+        None => writeln!(std::io::stdout().lock(), "{json}"),
+    }
+    .map_err(|err| err_write(err.to_string()))
+}
+
+fn db_get(
+    tree: &sled::Tree,
+    key: &[u8],
+) -> Result<Option<sled::IVec>, MaintenanceError> {
+    let query = tree
+        .get(key)
+        .map_err(|err| DbError::DbGet(hex::encode(key), err.to_string()))?;
+
+    Ok(query)
+}
+
+fn db_decode<T: Decode>(
+    tree: &sled::Tree,
+    key: &[u8],
+) -> Result<Option<T>, MaintenanceError> {
+    let Some(bytes) = db_get(tree, key)? else {
+        return Ok(None);
+    };
+
+    let value = T::decode(&mut &bytes[..]).map_err(|err| {
+        DbError::DecodeError(hex::encode(key), err.to_string())
+    })?;
+
+    Ok(Some(value))
+}
+
+fn db_insert(
+    tree: &sled::Tree,
+    key: &[u8],
+    value: Vec<u8>,
+) -> Result<(), MaintenanceError> {
+    tree.insert(key, value)
+        .map_err(|err| DbError::DbInsert(hex::encode(key), err.to_string()))?;
+
+    Ok(())
+}
+
+fn state_format(state: &ValidationState) -> String {
+    match state {
+        ValidationState::Unknown => "[   ..   ]".white().to_string(),
+        ValidationState::Pass => "[ Passed ]".green().to_string(),
+        ValidationState::Fail => "[ Failed ]".red().to_string(),
+        ValidationState::Timeout => "[Timeout ]".red().to_string(),
+        ValidationState::Blocked => "[Blocked ]".yellow().to_string(),
+    }
+}
@@ -0,0 +1,437 @@
+//! Upgrades databases created by earlier versions of the CLI.
+//!
+//! Values are SCALE-encoded, which carries no information about the layout of
+//! a type, so the version of the database schema is stored under
+//! [`KEY_VERSION`]. Databases are migrated to [`SCHEMA_VERSION`] as they are
+//! opened, one version at a time and each in a single transaction.
+//!
+//! When changing the layout of a stored type:
+//!
+//! 1. Copy its previous definition here, suffixed with the schema version it
+//!    belongs to, such as [`TestStateV1`].
+//! 2. Bump [`SCHEMA_VERSION`].
+//! 3. Add a [`Migration`] which decodes values with the previous definition
+//!    and stores them in the new layout.
+//!
+//! Databases without a version predate versioning and are at version 1.
+//!
+//! Only the tree of the course is versioned. The [`TREE_RUNS`] and
+//! [`TREE_OUTBOX`] trees are keyed by sequential ids, which cannot be iterated
+//! over in a transaction, and entries are never updated once written: runs are
+//! an append-only log and outbox entries are removed once delivered. Entries
+//! which cannot be decoded are skipped when reading them, and a change to their
+//! layout must be stored in a tree with a new name instead.
+//!
+//! [`TREE_RUNS`]: crate::db::TREE_RUNS
+//! [`TREE_OUTBOX`]: crate::db::TREE_OUTBOX
+
+use parity_scale_codec::{Decode, DecodeAll, Encode};
+use sled::transaction::{
+    ConflictableTransactionError, TransactionError, TransactionalTree,
+};
+
+use crate::db::{
+    DbError, PathLink, TestLimits, TestState, ValidationState, KEY_STAGGERED,
+    KEY_TESTS, KEY_VERSION,
+};
+
+/// Version of the database schema used by this version of the CLI.
+pub const SCHEMA_VERSION: u32 = 2;
+
+/// Key under which the time of the last modification of the course was stored,
+/// before tests were invalidated by content hash.
+const KEY_TIME_V1: &[u8] = b"time_last_modified";
+
+type MigrationResult = Result<(), ConflictableTransactionError<String>>;
+
+/// Upgrades the database from the previous schema version to `version`.
+struct Migration {
+    version: u32,
+    description: &'static str,
+    migrate: fn(&TransactionalTree) -> MigrationResult,
+}
+
+const MIGRATIONS: &[Migration] = &[Migration {
+    version: 2,
+    description: "tests with limits, expected output and dependencies",
+    migrate: migrate_v2,
+}];
+
+/// [`TestState`] as stored before schema versioning.
+#[derive(Encode, Decode, Debug, Clone)]
+pub struct TestStateV1 {
+    pub name: String,
+    pub slug: String,
+    pub message_on_success: String,
+    pub message_on_fail: String,
+    pub cmd: Vec<String>,
+    pub path: Vec<PathLink>,
+    pub passed: ValidationState,
+    pub optional: bool,
+    pub lesson_slug: String,
+}
+
+/// Migrates the database in `tree` to [`SCHEMA_VERSION`].
+pub fn db_migrate(tree: &sled::Tree) -> Result<(), DbError> {
+    // Fresh databases start at the latest version
+    let Some(version) = db_version(tree)? else {
+        tree.insert(KEY_VERSION, SCHEMA_VERSION.encode()).map_err(|err| {
+            DbError::DbInsert(hex::encode(KEY_VERSION), err.to_string())
+        })?;
+        return Ok(());
+    };
+
+    if version > SCHEMA_VERSION {
+        return Err(DbError::DbVersion(version, SCHEMA_VERSION));
+    }
+
+    for migration in MIGRATIONS.iter().filter(|m| m.version > version) {
+        log::debug!(
+            "migrating database to version {}: {}",
+            migration.version,
+            migration.description
+        );
+
+        tree.transaction(|tx| {
+            (migration.migrate)(tx)?;
+            tx.insert(KEY_VERSION, migration.version.encode())?;
+            Ok(())
+        })
+        .map_err(|err| {
+            let reason = match err {
+                TransactionError::Abort(reason) => reason,
+                TransactionError::Storage(err) => err.to_string(),
+            };
+            DbError::DbMigration(migration.version, reason)
+        })?;
+    }
+
+    Ok(())
+}
+
+/// Schema version of the database in `tree`, or `None` if it is empty.
+pub fn db_version(tree: &sled::Tree) -> Result<Option<u32>, DbError> {
+    let query = tree.get(KEY_VERSION).map_err(|err| {
+        DbError::DbGet(hex::encode(KEY_VERSION), err.to_string())
+    })?;
+
+    match query {
+        Some(bytes) => u32::decode(&mut &bytes[..]).map(Some).map_err(|err| {
+            DbError::DecodeError(hex::encode(KEY_VERSION), err.to_string())
+        }),
+        None if tree.is_empty() => Ok(None),
+        None => Ok(Some(1)),
+    }
+}
+
+/// Tests gain new fields, which take their default value, and staggered mode
+/// counts stages instead of tests. Tests used to run in order, so the stage of
+/// a test is its position in the course.
+///
+/// Fields which did not exist yet are only known once the course is loaded
+/// again, and are not compared when deciding whether a test changed, see
+/// [`db_update`](crate::db::db_update).
+fn migrate_v2(tx: &TransactionalTree) -> MigrationResult {
+    let keys = match tx.get(KEY_TESTS)? {
+        Some(bytes) => <Vec<Vec<u8>>>::decode(&mut &bytes[..])
+            .map_err(|err| abort(KEY_TESTS, err))?,
+        None => vec![],
+    };
+
+    for (stage, key) in keys.iter().enumerate() {
+        let Some(bytes) = tx.get(key)? else {
+            continue;
+        };
+
+        let test = match TestStateV1::decode_all(&mut &bytes[..]) {
+            Ok(test) => test,
+            // Tests which cannot be read are dropped and will be added back,
+            // without their state, the next time the course is loaded
+            Err(err) => {
+                log::debug!("dropping test {}: {err}", hex::encode(key));
+                tx.remove(key.as_slice())?;
+                continue;
+            }
+        };
+
+        let TestStateV1 {
+            name,
+            slug,
+            message_on_success,
+            message_on_fail,
+            cmd,
+            path,
+            passed,
+            optional,
+            lesson_slug,
+        } = test;
+
+        let test = TestState {
+            name,
+            slug,
+            message_on_success,
+            message_on_fail,
+            cmd,
+            cmd_error: None,
+            path,
+            passed,
+            optional,
+            section_slug: String::default(),
+            lesson_slug,
+            limits: TestLimits::default(),
+            independent: false,
+            env: vec![],
+            cwd: None,
+            stdin: None,
+            expected_stdout: None,
+            expected_stderr: None,
+            language: None,
+            depends_on: vec![],
+            stage: stage as u32,
+        };
+
+        tx.insert(key.as_slice(), test.encode())?;
+    }
+
+    // Staggered mode used to run the first `n` tests, that is every test
+    // before stage `n`, so the value stored is unchanged. The time of last
+    // modification is no longer used.
+    if tx.get(KEY_STAGGERED)?.is_none() {
+        tx.insert(KEY_STAGGERED, 1u32.encode())?;
+    }
+    tx.remove(KEY_TIME_V1)?;
+
+    Ok(())
+}
+
+fn abort(
+    key: &[u8],
+    err: parity_scale_codec::Error,
+) -> ConflictableTransactionError<String> {
+    ConflictableTransactionError::Abort(format!(
+        "failed to decode data stored at key '{}': {err}",
+        hex::encode(key)
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use indexmap::IndexMap;
+
+    use super::*;
+    use crate::{
+        db::{db_update, KEY_HASH, KEY_METADATA},
+        models::TesterDefinition,
+        parsing::CourseMetaData,
+    };
+
+    const COURSE: &str = r#"
+course_name: Course
+limits:
+  timeout: 60
+sections:
+  - name: Section
+    slug: section
+    lessons:
+      - name: Lesson
+        slug: lesson
+        duration: 1
+        tests:
+          - name: A
+            slug: a
+            optional: false
+            cmd: cargo test a
+            message_on_fail: ko
+            message_on_success: ok
+          - name: B
+            slug: b
+            optional: false
+            cmd: cargo test b
+            message_on_fail: ko
+            message_on_success: ok
+          - name: C
+            slug: c
+            optional: true
+            cmd: cargo test c
+            message_on_fail: ko
+            message_on_success: ok
+"#;
+
+    fn tree_temp() -> sled::Tree {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        db.open_tree(".").unwrap()
+    }
+
+    /// Writes the database the CLI wrote before schema versioning for
+    /// [`COURSE`], once its tests have been run with results `passed`.
+    fn db_v1(tree: &sled::Tree, passed: &[ValidationState]) {
+        let tests = [("A", "a", false), ("B", "b", false), ("C", "c", true)];
+        let mut keys = vec![];
+
+        for ((name, slug, optional), passed) in tests.iter().zip(passed) {
+            // Tests were keyed by their name and that of their lesson,
+            // section and course
+            let key = format!("{}lessonsectioncourse", name.to_lowercase());
+            let test = TestStateV1 {
+                name: name.to_string(),
+                slug: slug.to_string(),
+                message_on_success: "ok".to_string(),
+                message_on_fail: "ko".to_string(),
+                cmd: ["cargo", "test", slug].map(str::to_string).to_vec(),
+                path: vec![
+                    PathLink::Link("Section".to_string()),
+                    PathLink::Link("Lesson".to_string()),
+                    match optional {
+                        true => PathLink::LinkOptional(name.to_string()),
+                        false => PathLink::Link(name.to_string()),
+                    },
+                    PathLink::Link(name.to_string()),
+                ],
+                passed: passed.clone(),
+                optional: *optional,
+                lesson_slug: "lesson".to_string(),
+            };
+
+            tree.insert(key.as_bytes(), test.encode()).unwrap();
+            keys.push(key);
+        }
+
+        let metadata = CourseMetaData {
+            logstream_url: "logstream".to_string(),
+            logstream_id: "id".to_string(),
+            ws_url: "ws".to_string(),
+            tester_url: "tester".to_string(),
+        };
+
+        tree.insert(KEY_TESTS, keys.encode()).unwrap();
+        tree.insert(KEY_METADATA, metadata.encode()).unwrap();
+        tree.insert(KEY_TIME_V1, 1_700_000_000i64.encode()).unwrap();
+    }
+
+    fn tests_course() -> IndexMap<String, TestState> {
+        let tester = serde_yaml::from_str::<TesterDefinition>(COURSE).unwrap();
+        tester.list_tests().unwrap()
+    }
+
+    fn decode<T: Decode>(tree: &sled::Tree, key: &[u8]) -> Option<T> {
+        let bytes = tree.get(key).unwrap()?;
+        Some(T::decode(&mut &bytes[..]).unwrap())
+    }
+
+    fn tests_stored(tree: &sled::Tree) -> Vec<TestState> {
+        let keys = decode::<Vec<Vec<u8>>>(tree, KEY_TESTS).unwrap();
+        keys.iter().map(|key| decode(tree, key).unwrap()).collect()
+    }
+
+    #[test]
+    fn fresh_db_at_latest_version() {
+        let tree = tree_temp();
+
+        db_migrate(&tree).unwrap();
+
+        assert_eq!(decode::<u32>(&tree, KEY_VERSION), Some(SCHEMA_VERSION));
+    }
+
+    #[test]
+    fn migrates_v1() {
+        let tree = tree_temp();
+        db_v1(
+            &tree,
+            &[
+                ValidationState::Pass,
+                ValidationState::Fail,
+                ValidationState::Unknown,
+            ],
+        );
+        tree.insert(KEY_STAGGERED, 3u32.encode()).unwrap();
+
+        db_migrate(&tree).unwrap();
+
+        assert_eq!(decode::<u32>(&tree, KEY_VERSION), Some(SCHEMA_VERSION));
+
+        let tests = tests_stored(&tree);
+        let stages = tests.iter().map(|test| test.stage).collect::<Vec<_>>();
+
+        assert!(matches!(tests[0].passed, ValidationState::Pass));
+        assert!(matches!(tests[1].passed, ValidationState::Fail));
+        assert!(matches!(tests[2].passed, ValidationState::Unknown));
+        assert_eq!(stages, [0, 1, 2]);
+        assert_eq!(tests[0].slug, "a");
+        assert_eq!(tests[0].cmd, ["cargo", "test", "a"]);
+        assert_eq!(tests[0].lesson_slug, "lesson");
+        assert!(tests[0].depends_on.is_empty());
+        assert!(tests[0].cmd_error.is_none());
+        assert!(tests[2].optional);
+
+        // Staggered mode ran the first 3 tests, that is stages 0 to 2
+        assert_eq!(decode::<u32>(&tree, KEY_STAGGERED), Some(3));
+        assert!(tree.get(KEY_TIME_V1).unwrap().is_none());
+        assert!(tree.get(KEY_METADATA).unwrap().is_some());
+    }
+
+    #[test]
+    fn migrates_v1_without_staggered() {
+        let tree = tree_temp();
+        db_v1(&tree, &[ValidationState::Unknown]);
+
+        db_migrate(&tree).unwrap();
+
+        assert_eq!(decode::<u32>(&tree, KEY_STAGGERED), Some(1));
+    }
+
+    #[test]
+    fn migrated_tests_keep_progress() {
+        let tree = tree_temp();
+        db_v1(
+            &tree,
+            &[
+                ValidationState::Pass,
+                ValidationState::Pass,
+                ValidationState::Fail,
+            ],
+        );
+        tree.insert(KEY_STAGGERED, 3u32.encode()).unwrap();
+
+        db_migrate(&tree).unwrap();
+        let update = db_update(&tree, &tests_course(), None, "hash").unwrap();
+
+        let tests = tests_stored(&tree);
+        assert!(update.is_some_and(|update| update.is_empty()));
+        assert!(matches!(tests[0].passed, ValidationState::Pass));
+        assert!(matches!(tests[1].passed, ValidationState::Pass));
+        assert!(matches!(tests[2].passed, ValidationState::Fail));
+        assert_eq!(tests[0].section_slug, "section");
+        assert_eq!(tests[1].depends_on, ["lesson/a"]);
+        assert_eq!(decode::<u32>(&tree, KEY_STAGGERED), Some(3));
+        assert_eq!(decode::<String>(&tree, KEY_HASH).as_deref(), Some("hash"));
+    }
+
+    #[test]
+    fn drops_invalid_tests() {
+        let tree = tree_temp();
+        db_v1(&tree, &[ValidationState::Pass, ValidationState::Pass]);
+        tree.insert("blessonsectioncourse", vec![0xff]).unwrap();
+
+        db_migrate(&tree).unwrap();
+
+        assert!(decode::<TestState>(&tree, b"alessonsectioncourse").is_some());
+        assert!(tree.get("blessonsectioncourse").unwrap().is_none());
+    }
+
+    #[test]
+    fn rejects_newer_versions() {
+        let tree = tree_temp();
+        db_v1(&tree, &[ValidationState::Unknown]);
+        tree.insert(KEY_VERSION, (SCHEMA_VERSION + 1).encode()).unwrap();
+
+        let err = db_migrate(&tree).unwrap_err();
+
+        assert!(matches!(
+            err,
+            DbError::DbVersion(version, SCHEMA_VERSION)
+                if version == SCHEMA_VERSION + 1
+        ));
+        // Nothing is migrated
+        assert!(tree.get(KEY_TIME_V1).unwrap().is_some());
+    }
+}
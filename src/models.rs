@@ -1,14 +1,24 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 use bson::oid::ObjectId;
 use chrono::Utc;
 use indexmap::IndexMap;
+use itertools::Itertools;
 use serde::{Deserialize, Serialize};
 use strum_macros::Display;
 
 use crate::{
-    db::{PathLink, TestState, ValidationState},
-    parsing::v1::{no_empty_vec, JsonAuthorV1, JsonSectionV1},
+    db::{
+        PathLink, TestExpected, TestLanguage, TestLimits, TestState, TestStdin,
+        ValidationState,
+    },
+    parsing::{
+        v1::{
+            no_empty_vec, JsonAuthorV1, JsonLanguageV1, JsonLimitsV1,
+            JsonSectionV1,
+        },
+        ParsingError,
+    },
 };
 
 /// The type of document. This is used to identify the type of document in the
@@ -99,65 +109,401 @@ pub struct TesterDefinition {
     #[serde(deserialize_with = "no_empty_vec")]
     pub sections: Vec<JsonSectionV1>,
     pub course_name: String,
+    /// Course-wide default resource limits, overridden by per-test limits.
+    #[serde(default)]
+    pub limits: JsonLimitsV1,
+    /// Whether tests must run in a sandbox, without network access and with a
+    /// read-only filesystem outside of the tester workspace. Cargo can still
+    /// write to its home directory, but changes are discarded after each test,
+    /// so dependencies must already be downloaded and `--offline` used.
+    #[serde(default)]
+    pub sandbox: bool,
+    /// Language of the course, used to summarize failures reported by its
+    /// test harness.
+    #[serde(default)]
+    pub language: Option<JsonLanguageV1>,
 }
 
 impl TesterDefinition {
     // TODO: remove copy
-    pub fn list_tests(&self) -> IndexMap<String, TestState> {
-        let Self { sections, course_name, .. } = self;
+    pub fn list_tests(
+        &self,
+    ) -> Result<IndexMap<String, TestState>, ParsingError> {
+        let Self { sections, course_name, limits, language, .. } = self;
         log::debug!("Listing tests...");
 
-        sections.iter().fold(IndexMap::new(), |acc, section| {
+        // Paths are joined to the tester workspace when a test is run, and
+        // must not lead out of it
+        let path_invalid = sections
+            .iter()
+            .flat_map(|section| section.lessons.iter())
+            .flat_map(|lesson| lesson.tests.iter().flatten())
+            .find_map(|test| Some((test, test.path_outside_workspace()?)));
+        if let Some((test, path)) = path_invalid {
+            return Err(ParsingError::PathError(format!(
+                "path '{path}' of test '{}' leaves the tester workspace",
+                test.name
+            )));
+        }
+
+        let tests = sections.iter().fold(IndexMap::new(), |acc, section| {
             section.lessons.iter().fold(acc, |acc, lesson| {
                 match &lesson.tests {
-                    Some(tests) => tests.iter().fold(acc, |mut acc, test| {
-                        let key = [
-                            test.name.to_lowercase(),
-                            lesson.name.to_lowercase(),
-                            section.name.to_lowercase(),
-                            course_name.to_lowercase(),
-                        ]
-                        .concat();
-
-                        let cmd = test
-                            .cmd
-                            .split_whitespace()
-                            .map(|arg| arg.to_string())
-                            .collect::<Vec<_>>();
-
-                        let path = vec![
-                            PathLink::Link(section.name.clone()),
-                            PathLink::Link(lesson.name.clone()),
-                            if test.optional {
-                                PathLink::LinkOptional(test.name.clone())
-                            } else {
-                                PathLink::Link(test.name.clone())
-                            },
-                            if !test.optional && test.optional {
-                                PathLink::LinkOptional(test.name.clone())
-                            } else {
-                                PathLink::Link(test.name.clone())
-                            },
-                        ];
-
-                        let test = TestState {
-                            name: test.name.clone(),
-                            slug: test.slug.clone(),
-                            message_on_success: test.message_on_success.clone(),
-                            message_on_fail: test.message_on_fail.clone(),
-                            cmd,
-                            path,
-                            passed: ValidationState::Unknown,
-                            optional: test.optional,
-                            lesson_slug: lesson.slug.clone(),
-                        };
-
-                        acc.insert(key, test);
-                        acc
-                    }),
+                    Some(tests) => tests.iter().enumerate().fold(
+                        acc,
+                        |mut acc, (index_test, test)| {
+                            let key = [
+                                test.name.to_lowercase(),
+                                lesson.name.to_lowercase(),
+                                section.name.to_lowercase(),
+                                course_name.to_lowercase(),
+                            ]
+                            .concat();
+
+                            // Invalid commands do not prevent the course from
+                            // loading, the test fails with the error instead
+                            let (cmd, cmd_error) =
+                                match test.cmd.argv(test.shell) {
+                                    Ok(cmd) => (cmd, None),
+                                    Err(err) => {
+                                        log::debug!(
+                                            "invalid command for test '{}': \
+                                             {err}",
+                                            test.name
+                                        );
+                                        (vec![], Some(err.to_string()))
+                                    }
+                                };
+
+                            let path = vec![
+                                PathLink::Link(section.name.clone()),
+                                PathLink::Link(lesson.name.clone()),
+                                if test.optional {
+                                    PathLink::LinkOptional(test.name.clone())
+                                } else {
+                                    PathLink::Link(test.name.clone())
+                                },
+                                if !test.optional && test.optional {
+                                    PathLink::LinkOptional(test.name.clone())
+                                } else {
+                                    PathLink::Link(test.name.clone())
+                                },
+                            ];
+
+                            let JsonLimitsV1 {
+                                timeout,
+                                max_memory,
+                                max_output_bytes,
+                                max_processes,
+                            } = test.limits.or(limits);
+
+                            let depends_on =
+                                match (&test.depends_on, &lesson.depends_on) {
+                                    (Some(depends_on), _) => depends_on.clone(),
+                                    (None, Some(depends_on))
+                                        if index_test == 0 =>
+                                    {
+                                        depends_on.clone()
+                                    }
+                                    (None, _) => depends_on_default(
+                                        &acc,
+                                        test.independent,
+                                    ),
+                                };
+
+                            let test = TestState {
+                                name: test.name.clone(),
+                                slug: test.slug.clone(),
+                                message_on_success: test
+                                    .message_on_success
+                                    .clone(),
+                                message_on_fail: test.message_on_fail.clone(),
+                                cmd,
+                                cmd_error,
+                                path,
+                                passed: ValidationState::Unknown,
+                                optional: test.optional,
+                                section_slug: section.slug.clone(),
+                                lesson_slug: lesson.slug.clone(),
+                                limits: TestLimits {
+                                    timeout,
+                                    max_memory,
+                                    max_output_bytes,
+                                    max_processes,
+                                },
+                                independent: test.independent,
+                                env: test.env.clone().into_iter().collect(),
+                                cwd: test.cwd.clone(),
+                                stdin: test.stdin.clone().map(TestStdin::from),
+                                expected_stdout: test
+                                    .expected_stdout
+                                    .clone()
+                                    .map(TestExpected::from),
+                                expected_stderr: test
+                                    .expected_stderr
+                                    .clone()
+                                    .map(TestExpected::from),
+                                language: match language {
+                                    Some(JsonLanguageV1::Rust) => {
+                                        Some(TestLanguage::Rust)
+                                    }
+                                    Some(JsonLanguageV1::Go) => {
+                                        Some(TestLanguage::Go)
+                                    }
+                                    _ => None,
+                                },
+                                depends_on,
+                                stage: 0,
+                            };
+
+                            acc.insert(key, test);
+                            acc
+                        },
+                    ),
                     None => acc,
                 }
             })
+        });
+
+        tests_sort(tests)
+    }
+}
+
+/// Dependencies of a test defined after `tests` which does not list any.
+///
+/// Tests depend on the previous test by default, so that courses without
+/// dependencies run in order. Neighbouring independent tests must be able to
+/// run together instead: they depend on the last test before them which is not
+/// independent, and the test after them depends on all of them.
+fn depends_on_default(
+    tests: &IndexMap<String, TestState>,
+    independent: bool,
+) -> Vec<String> {
+    let batch = tests
+        .values()
+        .rev()
+        .take_while(|test| test.independent)
+        .map(TestState::id)
+        .collect::<Vec<_>>();
+    let previous =
+        tests.values().rev().find(|test| !test.independent).map(TestState::id);
+
+    match independent || batch.is_empty() {
+        true => previous.into_iter().collect(),
+        false => batch.into_iter().rev().collect(),
+    }
+}
+
+/// Orders `tests` so that each test comes after the tests it depends on, and
+/// sets the stage of each test: 0 for tests without dependencies, otherwise 1
+/// more than the highest stage of its dependencies. Tests otherwise stay in
+/// their order of definition.
+///
+/// Dependencies are replaced with the ids of the tests they refer to, see
+/// [`dependency_resolve`].
+fn tests_sort(
+    tests: IndexMap<String, TestState>,
+) -> Result<IndexMap<String, TestState>, ParsingError> {
+    let ids = tests.values().map(TestState::id).collect::<HashSet<_>>();
+    let slugs = tests.values().fold(
+        HashMap::<String, Vec<String>>::new(),
+        |mut acc, test| {
+            acc.entry(test.slug.clone()).or_default().push(test.id());
+            acc
+        },
+    );
+    let lessons = tests.values().fold(
+        HashMap::<String, Vec<String>>::new(),
+        |mut acc, test| {
+            acc.entry(test.lesson_slug.clone()).or_default().push(test.id());
+            acc
+        },
+    );
+
+    let mut tests = tests
+        .into_iter()
+        .map(|(key, mut test)| {
+            let depends_on = test
+                .depends_on
+                .iter()
+                .map(|reference| {
+                    dependency_resolve(&test, reference, &ids, &slugs, &lessons)
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+
+            // Tests can depend on the lesson they are part of
+            let id = test.id();
+            test.depends_on = depends_on
+                .into_iter()
+                .flatten()
+                .filter(|dependency| *dependency != id)
+                .unique()
+                .collect();
+
+            Ok((key, test))
         })
+        .collect::<Result<Vec<_>, ParsingError>>()?;
+
+    let mut stages = HashMap::<String, u32>::new();
+    let mut sorted = IndexMap::new();
+
+    while !tests.is_empty() {
+        let Some(index) = tests.iter().position(|(_, test)| {
+            test.depends_on.iter().all(|id| stages.contains_key(id))
+        }) else {
+            let names = tests
+                .iter()
+                .map(|(_, test)| format!("'{}'", test.name))
+                .join(", ");
+            return Err(ParsingError::DependencyError(format!(
+                "circular dependency between tests {names}"
+            )));
+        };
+
+        let (key, mut test) = tests.remove(index);
+        test.stage = test
+            .depends_on
+            .iter()
+            .map(|id| stages[id] + 1)
+            .max()
+            .unwrap_or_default();
+
+        stages.insert(test.id(), test.stage);
+        sorted.insert(key, test);
+    }
+
+    Ok(sorted)
+}
+
+/// Ids of the tests which `test` depends on through `reference`, which is
+/// either:
+///
+/// * a test, as 'lesson/test'.
+/// * the slug of a test in the same lesson as `test`.
+/// * the slug of a test in another lesson, as long as no other test has it.
+/// * the slug of a lesson, standing for every test in that lesson.
+///
+/// * `ids`: ids of every test in the course.
+/// * `slugs`: ids of the tests with a given slug.
+/// * `lessons`: ids of the tests in a given lesson.
+fn dependency_resolve(
+    test: &TestState,
+    reference: &String,
+    ids: &HashSet<String>,
+    slugs: &HashMap<String, Vec<String>>,
+    lessons: &HashMap<String, Vec<String>>,
+) -> Result<Vec<String>, ParsingError> {
+    if ids.contains(reference) {
+        return Ok(vec![reference.clone()]);
+    }
+
+    let id_local = format!("{}/{reference}", test.lesson_slug);
+    if ids.contains(&id_local) {
+        return Ok(vec![id_local]);
+    }
+
+    match (slugs.get(reference).map(Vec::as_slice), lessons.get(reference)) {
+        (Some([id]), _) => Ok(vec![id.clone()]),
+        (Some(ids), _) => Err(ParsingError::DependencyError(format!(
+            "test '{}' depends on '{reference}', which is the slug of tests \
+             {}: use 'lesson/test' instead",
+            test.name,
+            ids.iter().map(|id| format!("'{id}'")).join(", ")
+        ))),
+        (None, Some(lesson)) => Ok(lesson.clone()),
+        (None, None) => Err(ParsingError::DependencyError(format!(
+            "test '{}' depends on unknown slug '{reference}'",
+            test.name
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Course with lessons `l1` and `l2`, which both have tests `build` and
+    /// `run`, and lesson `l3` whose test `check` depends on `depends_on`.
+    fn course(depends_on: &str) -> String {
+        let lesson = |slug: &str, tests: &[(&str, Option<&str>)]| {
+            let tests = tests
+                .iter()
+                .map(|(test, depends_on)| {
+                    let depends_on = depends_on
+                        .map(|depends_on| format!(", depends_on: {depends_on}"))
+                        .unwrap_or_default();
+                    format!(
+                        "          - {{name: {slug} {test}, slug: {test}, \
+                         optional: false, cmd: 'true', message_on_fail: ko, \
+                         message_on_success: ok{depends_on}}}\n"
+                    )
+                })
+                .collect::<String>();
+
+            format!(
+                "      - name: {slug}\n        slug: {slug}\n        \
+                 duration: 1\n        tests:\n{tests}"
+            )
+        };
+
+        format!(
+            "course_name: Course\nsections:\n  - name: Section\n    slug: \
+             section\n    lessons:\n{}{}{}",
+            lesson("l1", &[("build", None), ("run", None)]),
+            lesson("l2", &[("build", Some("[]")), ("run", Some("[build]"))]),
+            lesson("l3", &[("check", Some(depends_on))]),
+        )
+    }
+
+    fn tests_list(
+        depends_on: &str,
+    ) -> Result<IndexMap<String, TestState>, ParsingError> {
+        let course = course(depends_on);
+        serde_yaml::from_str::<TesterDefinition>(&course).unwrap().list_tests()
+    }
+
+    fn test<'a>(
+        tests: &'a IndexMap<String, TestState>,
+        id: &str,
+    ) -> &'a TestState {
+        tests.values().find(|test| test.id() == id).unwrap()
+    }
+
+    #[test]
+    fn resolves_slugs_within_lesson() {
+        let tests = tests_list("[l1/run]").unwrap();
+
+        assert_eq!(test(&tests, "l1/run").depends_on, ["l1/build"]);
+        assert_eq!(test(&tests, "l2/run").depends_on, ["l2/build"]);
+        assert_eq!(test(&tests, "l2/build").stage, 0);
+        assert_eq!(test(&tests, "l2/run").stage, 1);
+    }
+
+    #[test]
+    fn resolves_qualified_slugs_and_lessons() {
+        let tests = tests_list("[l1/run]").unwrap();
+        assert_eq!(test(&tests, "l3/check").depends_on, ["l1/run"]);
+        assert_eq!(test(&tests, "l3/check").stage, 2);
+
+        let tests = tests_list("[l2]").unwrap();
+        assert_eq!(test(&tests, "l3/check").depends_on, ["l2/build", "l2/run"]);
+    }
+
+    #[test]
+    fn rejects_ambiguous_slugs() {
+        let err = tests_list("[run]").unwrap_err();
+
+        assert!(
+            matches!(&err, ParsingError::DependencyError(reason)
+                if reason.contains("'l1/run', 'l2/run'")),
+            "{err:?}"
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_slugs() {
+        let err = tests_list("[l4/run]").unwrap_err();
+
+        assert!(matches!(err, ParsingError::DependencyError(_)), "{err:?}");
     }
 }
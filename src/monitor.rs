@@ -1,38 +1,67 @@
+use std::collections::HashSet;
 use std::fs;
-use std::net::TcpStream;
 use std::path::{Path, PathBuf};
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::time::Duration;
 
 use indicatif::ProgressBar;
 
 use colored::Colorize;
-use ignore::Walk;
+use ignore::WalkBuilder;
 use itertools::{FoldWhile, Itertools};
+use notify::RecursiveMode;
+use notify_debouncer_mini::{new_debouncer, DebounceEventResult};
 use parity_scale_codec::{Decode, Encode};
-use rand::Rng;
 use reqwest::blocking::Client;
 use sled::IVec;
 use thiserror::Error;
-use tungstenite::{stream::MaybeTlsStream, Message, WebSocket};
 
+#[cfg(not(debug_assertions))]
+use crate::validator::{
+    v1::{ValidatorStateV1, ValidatorV1},
+    ValidatorVersion,
+};
 use crate::{
+    config::{Config, ConfigError},
     db::{
-        db_open, db_should_update, db_update, DbError, TestState, KEY_METADATA,
-        KEY_STAGGERED, KEY_TESTS,
+        db_open, db_should_update, db_update, hash_content, DbError, TestState,
+        ValidationState, KEY_METADATA, KEY_STAGGERED, KEY_TESTS,
     },
+    event::{self, Event},
+    history::{commit_head, History, HistoryError},
     lister::{v1::ListerV1, ListerVersion},
+    lock::LockError,
+    maintenance::MaintenanceError,
     models::TesterDefinition,
     parsing::{
-        load_course, load_repo, load_tester, CourseMetaData, JsonCourse,
-        JsonCourseVersion, MetadataError, ParsingError,
+        load_course, load_course_local, load_repo, load_tester,
+        load_tester_local, CourseMetaData, JsonCourse, JsonCourseVersion,
+        MetadataError, ParsingError,
     },
-    runner::{v1::RunnerV1Builder, RunnerVersion},
-    str_res::{DOTCODESCHOOL, STAGGERED},
-    validator::{
-        v1::{ValidatorStateV1, ValidatorV1},
-        ValidatorVersion,
+    report::ReportFormat,
+    reporter::{
+        file::FileReporter, history::HistoryReporter, json::JsonReporter,
+        outbox::Outbox, terminal::TerminalReporter, test_log::TestLogReporter,
+        ws::WsReporter, ReporterError, Reporters,
     },
+    runner::{
+        process::is_interrupted,
+        sandbox::{Sandbox, SandboxError},
+        v1::RunnerV1Builder,
+        RunnerVersion,
+    },
+    selection::{tests_select, SelectionError},
+    storage::StorageError,
+    str_res::{DOTCODESCHOOL, STAGGERED},
 };
 
+/// Prefix of the name of tester workspaces. Workspaces are hidden so that they
+/// are never copied into one another.
+const TESTER_PREFIX: &str = ".dcs-tester";
+/// Delay after the last file change before tests are re-run in watch mode.
+const WATCH_DEBOUNCE_MS: u64 = 500;
+const WATCH_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
 pub trait StateMachine {
     fn run(self) -> Self;
     fn is_finished(&self) -> bool;
@@ -43,13 +72,76 @@ pub enum MonitorError {
     #[error("{0}")]
     DbError(#[from] DbError),
     #[error("{0}")]
-    WSError(#[from] tungstenite::Error),
+    WSError(Box<tungstenite::Error>),
     #[error("{0}")]
     IOError(#[from] std::io::Error),
     #[error("{0}")]
     ParsingError(#[from] ParsingError),
     #[error("{0}")]
     MetadataError(#[from] MetadataError),
+    #[error("{0}")]
+    ConfigError(#[from] ConfigError),
+    #[error("{0}")]
+    ReporterError(#[from] ReporterError),
+    #[error("{0}")]
+    SandboxError(#[from] SandboxError),
+    #[error("{0}")]
+    SelectionError(#[from] SelectionError),
+    #[error("{0}")]
+    HistoryError(#[from] HistoryError),
+    #[error("{0}")]
+    MaintenanceError(Box<MaintenanceError>),
+    #[error("{0}")]
+    StorageError(Box<StorageError>),
+    #[error("{0}")]
+    LockError(#[from] LockError),
+    #[error("failed to watch files: {0}")]
+    WatchError(#[from] notify::Error),
+}
+
+// Large errors are boxed to keep `Result<_, MonitorError>` small.
+impl From<tungstenite::Error> for MonitorError {
+    fn from(err: tungstenite::Error) -> Self {
+        MonitorError::WSError(Box::new(err))
+    }
+}
+
+impl From<MaintenanceError> for MonitorError {
+    fn from(err: MaintenanceError) -> Self {
+        MonitorError::MaintenanceError(Box::new(err))
+    }
+}
+
+impl From<StorageError> for MonitorError {
+    fn from(err: StorageError) -> Self {
+        MonitorError::StorageError(Box::new(err))
+    }
+}
+
+/// Options which apply to every test run.
+#[derive(Debug, Clone, Default)]
+pub struct RunOptions {
+    /// Do not destroy the tester workspace once tests have run.
+    pub keep: bool,
+    /// Maximum number of independent tests to run concurrently.
+    pub jobs: usize,
+    /// Reports to write once tests have run.
+    pub reports: Vec<ReportFormat>,
+    /// Run tests in a sandbox, even if the course does not require it.
+    pub sandbox: bool,
+}
+
+/// Tests to run with [`Monitor::into_runner`].
+#[derive(Debug, Clone)]
+pub enum TestSelection {
+    /// Every test in the course.
+    All,
+    /// Tests matched by any of the given patterns, see [`crate::selection`].
+    Patterns(Vec<String>),
+    /// Tests which failed or timed out the last time they were run.
+    Failed,
+    /// Tests which have not been run yet, or were blocked by a failed test.
+    Unknown,
 }
 
 pub struct Monitor {
@@ -57,105 +149,184 @@ pub struct Monitor {
     tester: TesterDefinition,
     progress: ProgressBar,
     tree: sled::Tree,
+    /// Path to a local tester definition. When set, the monitor runs in
+    /// offline mode and never contacts the DotCodeSchool backend.
+    path_tester: Option<String>,
+    config: Config,
+    /// Websocket events which could not be delivered to DotCodeSchool.
+    outbox: Outbox,
+    /// Results of past test runs.
+    history: History,
 }
 
 impl Monitor {
-    pub fn new(path_db: &str) -> Result<Self, MonitorError> {
+    pub fn new(
+        path_db: &str,
+        path_tester: Option<String>,
+        config: Config,
+        refresh: bool,
+    ) -> Result<Self, MonitorError> {
         log::debug!("Creating new Monitor instance");
+
         let client = Client::new();
-        let course = load_course(&client)?;
-        let tester = load_tester(&client, &course)?;
-        let repo = load_repo()?;
-        let tests_new = tester.list_tests();
+        let (course, tester) = match &path_tester {
+            Some(path) => {
+                log::debug!("running in offline mode");
+                let tester = load_tester_local(path)?;
+                (load_course_local(&tester), tester)
+            }
+            None => {
+                let course = load_course(&client, &config)?;
+                let tester = load_tester(&client, &course)?;
+                (course, tester)
+            }
+        };
+        let tests_new = tester.list_tests()?;
+
+        let (db, tree) = db_open(path_db, ".")?;
+        let outbox = Outbox::open(&db)?;
+        let history = History::open(&db)?;
+
+        // Tests are only updated when the course changes, or if explicitly
+        // requested, so as to keep track of their state across runs
+        let hash = Self::course_hash(&course, &tester);
+        if refresh || db_should_update(&tree, &hash)? {
+            log::debug!("updating tests for course {hash}");
+
+            let metadata = match path_tester {
+                Some(_) => None,
+                None => Some(load_repo()?.fetch_metadata(&client, &config)?),
+            };
+            let update = db_update(&tree, &tests_new, metadata, &hash)?;
 
-        let (_, tree) = db_open(path_db, ".")?;
+            if let Some(update) = update.filter(|update| !update.is_empty()) {
+                log::debug!("{update}");
 
-        if db_should_update(&tree, ".")? {
-            let metadata = repo.fetch_metadata()?;
-            db_update(&tree, &tests_new, metadata)?;
+                if !event::is_json() {
+                    println!("\n📝 {}", update.to_string().yellow().bold());
+                }
+                event::emit(Event::CourseUpdated {
+                    added: update.added,
+                    changed: update.changed,
+                    removed: update.removed,
+                });
+            }
         }
 
         log::debug!("Monitor instance created successfully");
-        Ok(Self { course, progress: ProgressBar::new(0), tree, tester })
+        Ok(Self {
+            course,
+            progress: event::progress_bar(),
+            tree,
+            tester,
+            path_tester,
+            config,
+            outbox,
+            history,
+        })
     }
 
     pub fn into_runner(
         self,
-        test_name: Option<String>,
-        keep: bool,
+        selection: TestSelection,
+        options: RunOptions,
     ) -> Result<RunnerVersion, MonitorError> {
         self.greet();
 
-        let Self { course, progress, tree, .. } = self;
-
-        let tests = match test_name {
-            Some(test_name) => {
-                let mut path_to = test_name.split("/").collect::<Vec<_>>();
-                path_to.reverse();
-                let key = path_to.join("");
+        let Self {
+            course,
+            tester,
+            progress,
+            tree,
+            path_tester,
+            config,
+            outbox,
+            history,
+            ..
+        } = self;
+
+        let tests = match selection {
+            TestSelection::All => Self::tests_accumulate_all(&tree),
+            TestSelection::Patterns(patterns) => {
+                log::debug!("looking for tests which match {patterns:?}");
+
+                let tests = Self::tests_accumulate_all(&tree)
+                    .into_iter()
+                    .collect::<Result<Vec<_>, _>>()?;
+
+                tests_select(tests, &patterns)?.into_iter().map(Ok).collect()
+            }
+            TestSelection::Failed => {
+                log::debug!("looking for tests which failed");
 
-                log::debug!("looking for tests which match path '{key}'");
+                Self::tests_accumulate_state(&tree, |state| {
+                    matches!(
+                        state,
+                        ValidationState::Fail | ValidationState::Timeout
+                    )
+                })
+            }
+            TestSelection::Unknown => {
+                log::debug!("looking for tests which have not been run");
 
-                Self::tests_accumulate_matching(key, &tree)
+                Self::tests_accumulate_state(&tree, |state| {
+                    matches!(
+                        state,
+                        ValidationState::Unknown | ValidationState::Blocked
+                    )
+                })
             }
-            None => Self::tests_accumulate_all(&tree),
         }
         .into_iter()
         .collect::<Result<Vec<_>, _>>()?;
 
-        let metadata = match tree.get(KEY_METADATA) {
-            Ok(Some(bytes)) => CourseMetaData::decode(&mut &bytes[..])
-                .map_err(|e| {
-                    DbError::DecodeError(
-                        hex::encode(KEY_METADATA),
-                        e.to_string(),
-                    )
-                })?,
-            _ => {
-                return Err(DbError::DbGet(
-                    hex::encode(KEY_METADATA),
-                    String::default(),
-                )
-                .into());
-            }
-        };
-
-        log::debug!("initiating redis websocket stream");
-
-        let client =
-            Self::ws_stream_init(&metadata.ws_url, &metadata.logstream_id)?;
+        let workspace = Self::tester_init(&tree, &path_tester)?;
+        let sandbox = Self::sandbox_init(&tester, &workspace, &options)?;
+        let mut reporter = Self::reporters_init(
+            &tree,
+            &outbox,
+            &path_tester,
+            &config,
+            &progress,
+            &options,
+        )?;
+        reporter.push(HistoryReporter::new(history, commit_head()));
 
         match course {
             JsonCourseVersion::V1(_) => {
                 progress.set_length(tests.len() as u64);
 
-                let repo_name = Self::tester_repo_init(&metadata.tester_url)?;
-                let repo_name_1 = repo_name.clone();
+                let workspace_1 = workspace.clone();
                 let tree1 = tree.clone();
-                let staggered = tests.len() as u32;
+                let stages = tests
+                    .iter()
+                    .map(|(_, test)| test.stage)
+                    .collect::<Vec<_>>();
+                let stage_next =
+                    stages.iter().copied().max().map_or(1, |stage| stage + 1);
 
                 let runner = RunnerV1Builder::new()
                     .progress(progress)
-                    .target(repo_name)
+                    .target(workspace)
                     .tree(tree.clone())
-                    .client(client)
+                    .reporter(reporter)
+                    .jobs(options.jobs)
+                    .sandbox(sandbox)
                     .tests(tests)
-                    .on_pass(move || {
-                        let _ = tree.insert(KEY_STAGGERED, staggered.encode());
-                    })
+                    .on_pass(move || Self::stage_unlock(&tree, stage_next))
                     .on_fail(move |index_test| {
-                        // TODO: is this good UX?
-                        let staggered = (index_test + 1) as u32;
-                        let _ = tree1.insert(KEY_STAGGERED, staggered.encode());
+                        if let Some(stage) = stages.get(index_test) {
+                            Self::stage_unlock(&tree1, stage + 1);
+                        }
                     })
                     .on_finish(move || {
-                        if keep {
+                        if options.keep {
                             log::debug!(
                                 "keeping tester repo '{}'",
-                                &repo_name_1
+                                &workspace_1
                             );
                         } else {
-                            let _ = Self::tester_repo_destroy(&repo_name_1);
+                            let _ = Self::tester_repo_destroy(&workspace_1);
                         }
                     })
                     .build();
@@ -165,55 +336,104 @@ impl Monitor {
         }
     }
 
-    pub fn into_runner_staggered(
+    /// Runs the staggered test set, then re-runs it each time a file in the
+    /// working tree is saved.
+    ///
+    /// The tester workspace is kept alive between runs and only files which
+    /// have changed are copied over to it. Files ignored by git are never
+    /// copied, same as when the tester workspace is first created.
+    pub fn watch(
         self,
-        keep: bool,
-    ) -> Result<RunnerVersion, MonitorError> {
+        options: RunOptions,
+        path_db: &str,
+    ) -> Result<(), MonitorError> {
         self.greet();
 
-        let Self { course, progress, tree, tester, .. } = self;
+        let source = std::env::current_dir()?;
+        let workspace = Self::tester_init(&self.tree, &self.path_tester)?;
+        let mut tracked = Self::user_code_files(&source, &workspace);
+
+        let (tx, rx) = std::sync::mpsc::channel();
+        let mut debouncer =
+            new_debouncer(Duration::from_millis(WATCH_DEBOUNCE_MS), tx)?;
+        debouncer.watcher().watch(&source, RecursiveMode::Recursive)?;
+
+        loop {
+            let mut runner = self.runner_staggered(
+                event::progress_bar(),
+                workspace.clone(),
+                &options,
+                || {},
+            )?;
+
+            while !runner.is_finished() {
+                runner = runner.run();
+            }
 
-        progress.println(format!("\n{}", STAGGERED.clone()));
+            if is_interrupted() {
+                break;
+            }
 
-        let query = tree.get(KEY_STAGGERED).map_err(|err| {
-            DbError::DbGet(hex::encode(KEY_STAGGERED), err.to_string())
-        })?;
+            self.progress.println(format!(
+                "\n👀 {}",
+                "Watching for changes, press Ctrl-C to exit".white().dimmed()
+            ));
+
+            // Waits until a change affects the user's code, rather than files
+            // which are ignored by git
+            let mut synced = false;
+            while !synced {
+                let Some(changed) =
+                    Self::watch_changes(&rx, &source, &workspace, path_db)
+                else {
+                    return Self::tester_repo_destroy(&workspace);
+                };
+
+                synced = Self::tester_repo_sync(
+                    &source,
+                    &workspace,
+                    &changed,
+                    &mut tracked,
+                )?;
+            }
+        }
 
-        let staggered = match query {
-            Some(bytes) => u32::decode(&mut &bytes[..]).map_err(|err| {
-                DbError::DecodeError(
-                    hex::encode(KEY_STAGGERED),
-                    err.to_string(),
-                )
-            })?,
-            None => 1,
-        };
+        Self::tester_repo_destroy(&workspace)
+    }
 
-        let tests = Self::tests_accumulate_some(&tree, staggered as usize)
-            .into_iter()
-            .collect::<Result<Vec<_>, _>>()?;
+    /// Builds a runner over the staggered test set: all tests up to and
+    /// including the first stage which has not been passed yet.
+    fn runner_staggered<F>(
+        &self,
+        progress: ProgressBar,
+        workspace: String,
+        options: &RunOptions,
+        on_finish: F,
+    ) -> Result<RunnerVersion, MonitorError>
+    where
+        F: Fn() + 'static,
+    {
+        let Self {
+            course,
+            tree,
+            tester,
+            config,
+            path_tester,
+            outbox,
+            history,
+            ..
+        } = self;
 
-        let metadata = match tree.get(KEY_METADATA) {
-            Ok(Some(bytes)) => CourseMetaData::decode(&mut &bytes[..])
-                .map_err(|e| {
-                    DbError::DecodeError(
-                        hex::encode(KEY_METADATA),
-                        e.to_string(),
-                    )
-                })?,
-            _ => {
-                return Err(DbError::DbGet(
-                    hex::encode(KEY_METADATA),
-                    String::default(),
-                )
-                .into());
-            }
-        };
+        progress.println(format!("\n{}", STAGGERED.clone()));
 
-        log::debug!("initiating redis websocket stream");
+        let staggered = Self::stage_staggered(tree)?;
 
-        let client =
-            Self::ws_stream_init(&metadata.ws_url, &metadata.logstream_id)?;
+        let tests = Self::tests_accumulate_all(tree)
+            .into_iter()
+            .filter_ok(|(_, test)| test.stage < staggered)
+            .collect::<Result<Vec<_>, _>>()?;
+        let stages =
+            tests.iter().map(|(_, test)| test.stage).collect::<Vec<_>>();
 
         match course {
             JsonCourseVersion::V1(_) => {
@@ -229,32 +449,39 @@ impl Monitor {
 
                 progress.set_length(test_count as u64);
 
-                let repo_name = Self::tester_repo_init(&metadata.tester_url)?;
-                let repo_name_1 = repo_name.clone();
+                let tree0 = tree.clone();
                 let tree1 = tree.clone();
 
+                let sandbox = Self::sandbox_init(tester, &workspace, options)?;
+                let mut reporter = Self::reporters_init(
+                    tree,
+                    outbox,
+                    path_tester,
+                    config,
+                    &progress,
+                    options,
+                )?;
+                reporter
+                    .push(HistoryReporter::new(history.clone(), commit_head()));
+
                 let runner = RunnerV1Builder::new()
                     .progress(progress)
-                    .target(repo_name)
+                    .target(workspace)
                     .tree(tree.clone())
-                    .client(client)
+                    .reporter(reporter)
+                    .jobs(options.jobs)
+                    .sandbox(sandbox)
                     .tests(tests)
                     .on_pass(move || {
                         let staggered = staggered + 1;
-                        let _ = tree.insert(KEY_STAGGERED, staggered.encode());
+                        let _ = tree0.insert(KEY_STAGGERED, staggered.encode());
                     })
                     .on_fail(move |index_test| {
-                        // TODO: is this good UX?
-                        let staggered = (index_test + 1) as u32;
-                        let _ = tree1.insert(KEY_STAGGERED, staggered.encode());
-                    })
-                    .on_finish(move || {
-                        if keep {
-                            log::debug!("keeping tester repo '{repo_name_1}'");
-                        } else {
-                            let _ = Self::tester_repo_destroy(&repo_name_1);
+                        if let Some(stage) = stages.get(index_test) {
+                            Self::stage_unlock(&tree1, stage + 1);
                         }
                     })
+                    .on_finish(on_finish)
                     .build();
 
                 Ok(RunnerVersion::V1(runner))
@@ -334,25 +561,47 @@ impl Monitor {
         ));
     }
 
-    fn tests_accumulate_matching(
-        test_name: String,
-        tree: &sled::Tree,
-    ) -> Vec<Result<(IVec, TestState), DbError>> {
-        tree.scan_prefix(test_name)
-            .fold_while(vec![], |mut acc, query| match query {
-                Ok((key, bytes)) => {
-                    acc.push(match TestState::decode(&mut &bytes[..]) {
-                        Ok(test_state) => Ok((key, test_state)),
-                        Err(e) => Err(DbError::DecodeError(
-                            hex::encode(key),
-                            e.to_string(),
-                        )),
-                    });
-                    FoldWhile::Continue(acc)
-                }
-                Err(_) => FoldWhile::Done(vec![]),
-            })
-            .into_inner()
+    /// Fingerprint of the tester definition and course version.
+    fn course_hash(
+        course: &JsonCourseVersion,
+        tester: &TesterDefinition,
+    ) -> String {
+        let version = match course {
+            JsonCourseVersion::V1(course) => &course.version,
+        };
+        let tester = serde_json::to_vec(tester)
+            .unwrap_or_else(|_| format!("{tester:?}").into_bytes());
+
+        hash_content(&[version.as_bytes(), &tester])
+    }
+
+    /// Stage before which tests are run in staggered mode. Tests are grouped
+    /// into stages by their dependencies, starting at stage 0 with the tests
+    /// which do not depend on any other.
+    fn stage_staggered(tree: &sled::Tree) -> Result<u32, DbError> {
+        let query = tree.get(KEY_STAGGERED).map_err(|err| {
+            DbError::DbGet(hex::encode(KEY_STAGGERED), err.to_string())
+        })?;
+
+        match query {
+            Some(bytes) => u32::decode(&mut &bytes[..]).map_err(|err| {
+                DbError::DecodeError(
+                    hex::encode(KEY_STAGGERED),
+                    err.to_string(),
+                )
+            }),
+            None => Ok(1),
+        }
+    }
+
+    /// Unlocks every stage before `stage` in staggered mode. Never moves
+    /// staggered mode back to an earlier stage.
+    fn stage_unlock(tree: &sled::Tree, stage: u32) {
+        let staggered = match Self::stage_staggered(tree) {
+            Ok(staggered) => staggered.max(stage),
+            Err(_) => stage,
+        };
+        let _ = tree.insert(KEY_STAGGERED, staggered.encode());
     }
 
     fn tests_accumulate_all(
@@ -386,37 +635,42 @@ impl Monitor {
         tests.into_inner()
     }
 
-    fn tests_accumulate_some(
+    /// Accumulates all tests, in order, whose stored state satisfies `f`.
+    fn tests_accumulate_state<F>(
         tree: &sled::Tree,
-        n: usize,
-    ) -> Vec<Result<(IVec, TestState), DbError>> {
-        let test_names = match tree.get(KEY_TESTS) {
-            Ok(Some(bytes)) => <Vec<Vec<u8>>>::decode(&mut &bytes[..]).unwrap(),
-            _ => vec![],
-        };
-
-        let tests = test_names
+        f: F,
+    ) -> Vec<Result<(IVec, TestState), DbError>>
+    where
+        F: Fn(&ValidationState) -> bool,
+    {
+        Self::tests_accumulate_all(tree)
             .into_iter()
-            .take(n)
-            .map(|key| {
-                let query = tree.get(&key);
-                (IVec::from(key), query)
+            .filter_ok(|(_, test)| f(&test.passed))
+            .collect()
+    }
+
+    /// Walks the user's code in `source`, respecting `.gitignore` rules and
+    /// skipping the tester workspace `dest_dir_name` itself.
+    fn user_code_walk(source: &Path, dest_dir_name: &str) -> ignore::Walk {
+        let dest_dir_name = dest_dir_name.to_string();
+
+        WalkBuilder::new(source)
+            .filter_entry(move |entry| {
+                entry.file_name() != dest_dir_name.as_str()
             })
-            .fold_while(vec![], |mut acc, (key, query)| match query {
-                Ok(Some(bytes)) => {
-                    acc.push(match TestState::decode(&mut &bytes[..]) {
-                        Ok(test_state) => Ok((key, test_state)),
-                        Err(e) => Err(DbError::DecodeError(
-                            hex::encode(key),
-                            e.to_string(),
-                        )),
-                    });
-                    FoldWhile::Continue(acc)
-                }
-                _ => FoldWhile::Done(vec![]),
-            });
+            .build()
+    }
 
-        tests.into_inner()
+    /// Lists the user's files which would be copied to the tester workspace,
+    /// relative to `source`.
+    fn user_code_files(source: &Path, dest_dir_name: &str) -> HashSet<PathBuf> {
+        Self::user_code_walk(source, dest_dir_name)
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.path().is_file())
+            .filter_map(|entry| {
+                entry.path().strip_prefix(source).ok().map(Path::to_path_buf)
+            })
+            .collect()
     }
 
     fn copy_user_code_to_tester(
@@ -430,16 +684,14 @@ impl Monitor {
         let dest_dir_name =
             destination_path.file_name().unwrap().to_str().unwrap();
 
-        for entry in Walk::new(source_path) {
+        for entry in Self::user_code_walk(source_path, dest_dir_name) {
             let entry = entry.map_err(|e| {
                 std::io::Error::new(std::io::ErrorKind::Other, e)
             })?;
             let path = entry.path();
 
-            // Skip the root directory itself and the destination directory
-            if path == source_path
-                || path.file_name().map_or(false, |name| name == dest_dir_name)
-            {
+            // Skip the root directory itself
+            if path == source_path {
                 continue;
             }
 
@@ -459,45 +711,348 @@ impl Monitor {
         Ok(())
     }
 
-    fn ws_stream_init(
-        ws_url: &str,
-        logstream_id: &str,
-    ) -> Result<WebSocket<MaybeTlsStream<TcpStream>>, MonitorError> {
-        // TODO: use https://docs.rs/zeroize/latest/zeroize/ to handle ws address
-        // + should be received from initial curl response
-        let (mut client, _) = tungstenite::client::connect(ws_url)?;
-        client.send(Message::Text(format!(
-            "{{\"event_type\":\"init\",\"stream_id\":\"{}\"}}",
-            logstream_id
-        )))?;
+    /// Blocks until files in the user's working tree have changed, returning
+    /// their paths relative to `source`. Changes to the tester workspace and
+    /// the database are ignored. Returns `None` if the user interrupted the
+    /// CLI.
+    fn watch_changes(
+        rx: &Receiver<DebounceEventResult>,
+        source: &Path,
+        workspace: &str,
+        path_db: &str,
+    ) -> Option<Vec<PathBuf>> {
+        let path_db = Path::new(path_db);
+        let path_db = path_db.strip_prefix(".").unwrap_or(path_db);
+
+        loop {
+            match rx.recv_timeout(WATCH_POLL_INTERVAL) {
+                Ok(Ok(events)) => {
+                    let changed = events
+                        .into_iter()
+                        .filter_map(|event| {
+                            event
+                                .path
+                                .strip_prefix(source)
+                                .ok()
+                                .map(Path::to_path_buf)
+                        })
+                        .filter(|path| {
+                            !path.starts_with(workspace)
+                                && !path.starts_with(path_db)
+                        })
+                        .unique()
+                        .collect::<Vec<_>>();
+
+                    if !changed.is_empty() {
+                        return Some(changed);
+                    }
+                }
+                Ok(Err(err)) => log::error!("failed to watch files: {err:?}"),
+                Err(RecvTimeoutError::Timeout) if is_interrupted() => {
+                    return None
+                }
+                Err(RecvTimeoutError::Timeout) => continue,
+                Err(RecvTimeoutError::Disconnected) => return None,
+            }
+        }
+    }
+
+    /// Copies `changed` files from the user's working tree over to the tester
+    /// workspace. Files which were deleted by the user are also deleted from
+    /// the tester workspace, as long as they had been copied there previously.
+    /// Returns whether any file was synced.
+    ///
+    /// * `tracked`: user files currently copied to the tester workspace.
+    fn tester_repo_sync(
+        source: &Path,
+        workspace: &str,
+        changed: &[PathBuf],
+        tracked: &mut HashSet<PathBuf>,
+    ) -> Result<bool, MonitorError> {
+        let tracked_new = Self::user_code_files(source, workspace);
+        let destination = Path::new(workspace);
+        let mut synced = false;
+
+        for path in changed {
+            let dest_path = destination.join(path);
+
+            if tracked_new.contains(path) {
+                log::debug!("syncing '{}' to tester repo", path.display());
+
+                if let Some(parent) = dest_path.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                fs::copy(source.join(path), &dest_path)?;
+                synced = true;
+            } else if tracked.contains(path) {
+                log::debug!("removing '{}' from tester repo", path.display());
+
+                if dest_path.is_file() {
+                    fs::remove_file(&dest_path)?;
+                }
+                synced = true;
+            }
+        }
+
+        *tracked = tracked_new;
+
+        Ok(synced)
+    }
+
+    fn metadata_load(tree: &sled::Tree) -> Result<CourseMetaData, DbError> {
+        match tree.get(KEY_METADATA) {
+            Ok(Some(bytes)) => {
+                CourseMetaData::decode(&mut &bytes[..]).map_err(|e| {
+                    DbError::DecodeError(
+                        hex::encode(KEY_METADATA),
+                        e.to_string(),
+                    )
+                })
+            }
+            _ => Err(DbError::DbGet(
+                hex::encode(KEY_METADATA),
+                String::default(),
+            )),
+        }
+    }
+
+    /// Checks that tests can be sandboxed if the course or the student
+    /// requested it.
+    fn sandbox_init(
+        tester: &TesterDefinition,
+        workspace: &str,
+        options: &RunOptions,
+    ) -> Result<Option<Sandbox>, MonitorError> {
+        if !options.sandbox && !tester.sandbox {
+            return Ok(None);
+        }
+
+        log::debug!("sandboxing tests in '{workspace}'");
+
+        match Sandbox::new(Path::new(workspace)) {
+            Ok(sandbox) => Ok(Some(sandbox)),
+            Err(err) => {
+                let _ = Self::tester_repo_destroy(workspace);
+                Err(err.into())
+            }
+        }
+    }
+
+    /// Selects where test results are reported to during a run.
+    ///
+    /// Results are always displayed to the student, either as human-readable
+    /// text or as json events. They are also sent to DotCodeSchool unless in
+    /// offline mode, and written to any report requested with `--report`.
+    fn reporters_init(
+        tree: &sled::Tree,
+        outbox: &Outbox,
+        path_tester: &Option<String>,
+        config: &Config,
+        progress: &ProgressBar,
+        options: &RunOptions,
+    ) -> Result<Reporters, MonitorError> {
+        let mut reporters = Reporters::new();
+
+        if event::is_json() {
+            reporters.push(JsonReporter);
+        } else {
+            reporters.push(TerminalReporter::new(progress.clone()));
+        }
 
-        Ok(client)
+        if path_tester.is_none() {
+            let metadata = Self::metadata_load(tree)?;
+
+            reporters.push(WsReporter::new(
+                metadata.ws_url,
+                metadata.logstream_id,
+                outbox.clone(),
+            ));
+            reporters.push(TestLogReporter::new(
+                Client::new(),
+                config.backend_url().to_string(),
+                crate::parsing::extract_repo_name()?,
+            ));
+        }
+
+        if !options.reports.is_empty() {
+            reporters.push(FileReporter::new(options.reports.clone()));
+        }
+
+        Ok(reporters)
+    }
+
+    /// Prepares a tester workspace for this run, returning its name.
+    ///
+    /// In offline mode, the tester workspace is created from the directory
+    /// containing the local tester definition.
+    fn tester_init(
+        tree: &sled::Tree,
+        path_tester: &Option<String>,
+    ) -> Result<String, MonitorError> {
+        Self::tester_workspaces_prune(Path::new("."));
+
+        match path_tester {
+            Some(path) => {
+                let dir_tester = match Path::new(path).parent() {
+                    Some(parent) if !parent.as_os_str().is_empty() => parent,
+                    _ => Path::new("."),
+                };
+
+                Self::tester_repo_init_local(dir_tester)
+            }
+            None => {
+                let metadata = Self::metadata_load(tree)?;
+                Self::tester_repo_init(&metadata.tester_url)
+            }
+        }
     }
 
     fn tester_repo_init(repo_url: &str) -> Result<String, MonitorError> {
-        // Extract the repo name from the git config
-        let repo_name = crate::parsing::extract_repo_name()?;
+        let workspace = Self::tester_workspace_name();
 
         std::process::Command::new("git")
             .arg("clone")
             .arg(repo_url)
-            .arg(&repo_name)
+            .arg(&workspace)
             .output()?;
 
         // Copy user's code to the tester directory
         let current_dir = std::env::current_dir()?;
         Self::copy_user_code_to_tester(
             current_dir.to_str().unwrap(),
-            &repo_name,
+            &workspace,
+        )?;
+
+        Ok(workspace)
+    }
+
+    fn tester_repo_init_local(
+        dir_tester: &Path,
+    ) -> Result<String, MonitorError> {
+        let workspace = Self::tester_workspace_name();
+
+        log::debug!("copying local tester from '{}'", dir_tester.display());
+
+        fs::create_dir_all(&workspace)?;
+        Self::copy_user_code_to_tester(
+            dir_tester.to_str().unwrap(),
+            &workspace,
+        )?;
+
+        // Copy user's code to the tester directory
+        let current_dir = std::env::current_dir()?;
+        Self::copy_user_code_to_tester(
+            current_dir.to_str().unwrap(),
+            &workspace,
         )?;
 
-        Ok(repo_name)
+        Ok(workspace)
+    }
+
+    /// Name of a tester workspace unique to this run, so that runs never share
+    /// a workspace or clone into one kept with `--keep`.
+    fn tester_workspace_name() -> String {
+        format!(
+            "{TESTER_PREFIX}-{}-{}",
+            chrono::Utc::now().timestamp(),
+            std::process::id()
+        )
+    }
+
+    /// Pid of the run which created the tester workspace `name`, if `name` is
+    /// that of a tester workspace.
+    fn tester_workspace_pid(name: &str) -> Option<u32> {
+        let suffix = name.strip_prefix(TESTER_PREFIX)?.strip_prefix('-')?;
+        let (timestamp, pid) = suffix.split_once('-')?;
+        timestamp.parse::<i64>().ok()?;
+
+        pid.parse().ok()
+    }
+
+    /// Removes the tester workspaces in `dir` left behind by runs which have
+    /// since exited, either because they were interrupted or kept with
+    /// `--keep`.
+    fn tester_workspaces_prune(dir: &Path) {
+        let Ok(entries) = fs::read_dir(dir) else {
+            return;
+        };
+
+        for entry in entries.flatten() {
+            let name = entry.file_name();
+            let Some(pid) = name.to_str().and_then(Self::tester_workspace_pid)
+            else {
+                continue;
+            };
+
+            let is_dir = entry.file_type().is_ok_and(|kind| kind.is_dir());
+            if is_dir && pid != std::process::id() && !process_alive(pid) {
+                log::debug!("removing stale tester workspace {name:?}");
+                let _ = fs::remove_dir_all(entry.path());
+            }
+        }
     }
 
-    fn tester_repo_destroy(repo_name: &str) -> Result<(), MonitorError> {
-        let path = format!("./{repo_name}");
+    fn tester_repo_destroy(workspace: &str) -> Result<(), MonitorError> {
+        let path = format!("./{workspace}");
         std::fs::remove_dir_all(path)?;
 
         Ok(())
     }
 }
+
+/// Whether a process with this pid is still running. Processes owned by
+/// another user are considered to be running.
+fn process_alive(pid: u32) -> bool {
+    let Ok(pid) = libc::pid_t::try_from(pid) else {
+        return false;
+    };
+
+    // SAFETY: signal 0 only checks that the process exists, nothing is sent.
+    if unsafe { libc::kill(pid, 0) } == 0 {
+        return true;
+    }
+
+    std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_tester_workspace_pids() {
+        assert_eq!(
+            Monitor::tester_workspace_pid(".dcs-tester-1718000000-4242"),
+            Some(4242)
+        );
+        assert_eq!(Monitor::tester_workspace_pid(".dcs-tester"), None);
+        assert_eq!(Monitor::tester_workspace_pid(".dcs-tester-4242"), None);
+        assert_eq!(Monitor::tester_workspace_pid(".dcs-tester-x-4242"), None);
+        assert_eq!(Monitor::tester_workspace_pid("src"), None);
+    }
+
+    #[test]
+    fn prunes_workspaces_of_exited_runs() {
+        let mut child = std::process::Command::new("true").spawn().unwrap();
+        let pid_exited = child.id();
+        child.wait().unwrap();
+
+        let dir = std::env::temp_dir()
+            .join(format!("dcs-monitor-prune-{}", std::process::id()));
+        let stale = dir.join(format!("{TESTER_PREFIX}-1-{pid_exited}"));
+        let current =
+            dir.join(format!("{TESTER_PREFIX}-1-{}", std::process::id()));
+        let other = dir.join("src");
+        for path in [&stale, &current, &other] {
+            fs::create_dir_all(path).unwrap();
+        }
+
+        Monitor::tester_workspaces_prune(&dir);
+
+        assert!(!stale.exists());
+        assert!(current.exists());
+        assert!(other.exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -8,16 +8,17 @@ use git2::Repository;
 use parity_scale_codec::{Decode, Encode};
 use reqwest::blocking::Client;
 use serde::{Deserialize, Serialize};
-use std::path::Path;
+use std::{fmt::Display, path::Path, time::Duration};
 use thiserror::Error;
 use v1::JsonRepoV1;
 
 use crate::{
-    constants::BACKEND_URL,
+    config::Config,
     models::{
         Course, Relationship, Repository as RepositoryModel, TesterDefinition,
     },
-    parsing::v1::JsonCourseV1,
+    parsing::v1::{JsonAuthorV1, JsonCourseV1},
+    runner::{expect::OutputMismatch, failures::TestFailure},
 };
 
 pub mod v1;
@@ -42,19 +43,81 @@ pub enum ParsingError {
     GitError(#[from] git2::Error),
     #[error("YAML parsing error: {0}")]
     YamlError(#[from] serde_yaml::Error),
+    #[error("failed to read tester definition at '{0}': {1}")]
+    TesterReadError(String, String),
+    #[error("invalid test dependencies: {0}")]
+    DependencyError(String),
+    #[error("invalid test path: {0}")]
+    PathError(String),
 }
 
 #[derive(Error, Debug)]
 pub enum MetadataError {
     #[error("failed to retrieve course metadata: {0}")]
     MetadataRetrievalError(String),
+    #[error("failed to retrieve course metadata, HTTP error: {0}")]
+    Http(reqwest::StatusCode),
+    #[error("failed to retrieve course metadata, request timed out: {0}")]
+    Timeout(String),
     #[error("Invalid course metadata format: {0}")]
     MetadataFmtError(String),
 }
 
+impl From<reqwest::Error> for MetadataError {
+    fn from(err: reqwest::Error) -> Self {
+        if err.is_timeout() {
+            MetadataError::Timeout(err.to_string())
+        } else if err.is_decode() {
+            MetadataError::MetadataFmtError(err.to_string())
+        } else if let Some(status) = err.status() {
+            MetadataError::Http(status)
+        } else {
+            MetadataError::MetadataRetrievalError(err.to_string())
+        }
+    }
+}
+
+/// Output captured while running a test.
+#[derive(Debug, Default, Clone)]
+pub struct TestOutput {
+    pub stdout: String,
+    pub stderr: String,
+    pub duration: Duration,
+    /// Differences between the output of the test and what was expected.
+    pub mismatches: Vec<OutputMismatch>,
+    /// Individual failing tests reported by the test harness.
+    pub failures: Vec<TestFailure>,
+}
+
+impl TestOutput {
+    /// Output of a test which could not be run at all.
+    pub fn error(msg: &str) -> Self {
+        Self { stderr: msg.to_string(), ..Default::default() }
+    }
+}
+
+impl Display for TestOutput {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "\n{}\n{}", self.stderr, self.stdout)
+    }
+}
+
 pub enum TestResult {
-    Pass(String),
-    Fail(String),
+    Pass(TestOutput),
+    Fail(TestOutput),
+    /// The test was stopped after exceeding its timeout. This is reported as
+    /// a failure.
+    Timeout(TestOutput, Duration),
+}
+
+impl TestResult {
+    pub fn output(&self) -> &TestOutput {
+        match self {
+            TestResult::Pass(output)
+            | TestResult::Fail(output)
+            | TestResult::Timeout(output, _) => output,
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Encode, Decode, Debug, Default)]
@@ -113,11 +176,13 @@ pub fn extract_repo_name() -> Result<String, ParsingError> {
 
 fn fetch_course(
     client: &Client,
+    config: &Config,
     course_id: &str,
 ) -> Result<Course, ParsingError> {
     log::debug!("Fetching course with id `{}`", course_id);
-    let response =
-        client.get(format!("{}/course/{}", BACKEND_URL, course_id)).send()?;
+    let response = client
+        .get(format!("{}/course/{}", config.backend_url(), course_id))
+        .send()?;
 
     if !response.status().is_success() {
         log::error!(
@@ -143,11 +208,12 @@ fn fetch_course(
 
 fn fetch_repository(
     client: &Client,
+    config: &Config,
     repo_name: &str,
 ) -> Result<RepositoryModel, ParsingError> {
     log::debug!("Fetching repository details for `{}`", repo_name);
     let response = client
-        .get(format!("{}/repository/{}", BACKEND_URL, repo_name))
+        .get(format!("{}/repository/{}", config.backend_url(), repo_name))
         .send()?;
 
     if !response.status().is_success() {
@@ -169,11 +235,14 @@ fn fetch_repository(
     response_text
 }
 
-pub fn load_course(client: &Client) -> Result<JsonCourseVersion, ParsingError> {
+pub fn load_course(
+    client: &Client,
+    config: &Config,
+) -> Result<JsonCourseVersion, ParsingError> {
     log::debug!("Starting to load course");
 
     let repo_name = extract_repo_name()?;
-    let repo_data = fetch_repository(client, &repo_name)?;
+    let repo_data = fetch_repository(client, config, &repo_name)?;
 
     let course_relation: &Relationship =
         repo_data.relationships.get("course").ok_or(()).map_err(|_| {
@@ -184,7 +253,7 @@ pub fn load_course(client: &Client) -> Result<JsonCourseVersion, ParsingError> {
         })?;
 
     let course_data: Course =
-        fetch_course(client, &course_relation.id.to_string())?;
+        fetch_course(client, config, &course_relation.id.to_string())?;
 
     log::debug!("Parsing course data");
 
@@ -255,6 +324,42 @@ pub fn load_tester(
     Ok(tester_definition)
 }
 
+/// Loads a tester definition from a local `tester-definition.yml` file
+/// instead of fetching it from the course tester repository.
+pub fn load_tester_local(path: &str) -> Result<TesterDefinition, ParsingError> {
+    log::debug!("Loading local tester definition from: {}", path);
+
+    let yaml_content = std::fs::read_to_string(path).map_err(|err| {
+        ParsingError::TesterReadError(path.to_string(), err.to_string())
+    })?;
+
+    let tester_definition: TesterDefinition =
+        serde_yaml::from_str(&yaml_content)?;
+    log::debug!("Successfully parsed local tester definition");
+
+    Ok(tester_definition)
+}
+
+/// Derives course information from a local tester definition. This is used in
+/// offline mode, where no course data can be retrieved from the backend.
+pub fn load_course_local(tester: &TesterDefinition) -> JsonCourseVersion {
+    log::debug!("Deriving course from local tester definition");
+
+    let json_course_v1 = JsonCourseV1 {
+        version: V_1_0.to_string(),
+        slug: String::default(),
+        name: tester.course_name.clone(),
+        author: JsonAuthorV1 {
+            name: "(offline)".to_string(),
+            url: String::default(),
+        },
+        title: tester.course_name.clone(),
+        tester_url: String::default(),
+    };
+
+    JsonCourseVersion::V1(json_course_v1)
+}
+
 pub fn load_repo() -> Result<JsonRepoV1, ParsingError> {
     Ok(JsonRepoV1 { name: extract_repo_name()?, commit_sha: "".to_string() })
 }
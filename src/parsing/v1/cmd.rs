@@ -0,0 +1,222 @@
+//! Test commands, as written in the tester definition.
+//!
+//! A command is either a list of arguments, which is executed as is, or a
+//! single string which is split into arguments following POSIX shell quoting
+//! rules:
+//!
+//! ```yaml
+//! cmd: ["cargo", "test", "--", "--exact", "tests::it works"]
+//! cmd: cargo test -- --exact 'tests::it works'
+//! ```
+//!
+//! Quoting is the only shell feature supported when splitting a string. Tests
+//! which rely on pipes, redirections, variables or `&&` chains must opt into
+//! running through `sh -c` with `shell: true`, and `check` warns about commands
+//! which look like they do:
+//!
+//! ```yaml
+//! cmd: RUST_LOG=debug cargo test 2>&1 | grep -q 'test result: ok'
+//! shell: true
+//! ```
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum CmdError {
+    #[error("command is empty")]
+    Empty,
+    #[error("unterminated quote in command '{0}'")]
+    UnterminatedQuote(String),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum JsonCmdV1 {
+    Args(Vec<String>),
+    Line(String),
+}
+
+impl Default for JsonCmdV1 {
+    fn default() -> Self {
+        Self::Line(String::default())
+    }
+}
+
+impl JsonCmdV1 {
+    /// Arguments the test process is spawned with, the first one being the
+    /// program to run. If `shell` is set, a command line is passed as is to
+    /// `sh -c`.
+    pub fn argv(&self, shell: bool) -> Result<Vec<String>, CmdError> {
+        let argv = match self {
+            Self::Args(args) => args.clone(),
+            Self::Line(line) if line.trim().is_empty() => vec![],
+            Self::Line(line) if shell => {
+                vec!["sh".to_string(), "-c".to_string(), line.clone()]
+            }
+            Self::Line(line) => shlex::split(line)
+                .ok_or_else(|| CmdError::UnterminatedQuote(line.clone()))?,
+        };
+
+        match argv.first() {
+            Some(program) if !program.is_empty() => Ok(argv),
+            _ => Err(CmdError::Empty),
+        }
+    }
+
+    /// Why the command might not run the way its author intended, even though
+    /// it can be parsed.
+    #[cfg(any(test, not(debug_assertions)))]
+    pub fn warning(&self, shell: bool) -> Option<String> {
+        match self {
+            Self::Args(_) if shell => Some(
+                "`shell: true` has no effect on a list of arguments"
+                    .to_string(),
+            ),
+            Self::Line(line) if !shell => shell_syntax(line).map(|syntax| {
+                format!(
+                    "command uses {syntax}, which is passed to the test as is \
+                     unless `shell: true` is set"
+                )
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// Looks for shell syntax outside of quotes in `line`, which would be passed
+/// as literal arguments to the test process instead of being interpreted.
+#[cfg(any(test, not(debug_assertions)))]
+fn shell_syntax(line: &str) -> Option<String> {
+    let line = line.trim_start();
+
+    let name_len = line
+        .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+        .unwrap_or(line.len());
+    if name_len > 0 && line[name_len..].starts_with('=') {
+        return Some(format!(
+            "an environment variable assignment ('{}=')",
+            &line[..name_len]
+        ));
+    }
+
+    let (mut quote_single, mut quote_double) = (false, false);
+    let mut chars = line.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' if !quote_double => quote_single = !quote_single,
+            '"' if !quote_single => quote_double = !quote_double,
+            '\\' if !quote_single => {
+                chars.next();
+            }
+            '$' | '`' if !quote_single => {
+                return Some(format!("a substitution ('{c}')"))
+            }
+            '|' | '&' | ';' | '<' | '>' | '(' | ')'
+                if !quote_single && !quote_double =>
+            {
+                return Some(format!("an operator ('{c}')"))
+            }
+            '*' | '?' if !quote_single && !quote_double => {
+                return Some(format!("a glob pattern ('{c}')"))
+            }
+            _ => {}
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn line(line: &str) -> JsonCmdV1 {
+        JsonCmdV1::Line(line.to_string())
+    }
+
+    #[test]
+    fn flags_shell_syntax() {
+        let flagged = [
+            ("RUST_LOG=debug cargo test", "an environment variable"),
+            ("echo $HOME", "a substitution ('$')"),
+            ("echo \"$HOME\"", "a substitution ('$')"),
+            ("echo `pwd`", "a substitution ('`')"),
+            ("cargo test | grep ok", "an operator ('|')"),
+            ("cargo build && cargo test", "an operator ('&')"),
+            ("cargo build; cargo test", "an operator (';')"),
+            ("cargo run < input.txt", "an operator ('<')"),
+            ("cargo test > out.txt", "an operator ('>')"),
+            ("(cargo test)", "an operator ('(')"),
+            ("cat *.rs", "a glob pattern ('*')"),
+            ("cat src/?.rs", "a glob pattern ('?')"),
+        ];
+
+        for (cmd, syntax) in flagged {
+            let found = shell_syntax(cmd);
+            assert!(
+                found.as_deref().is_some_and(|found| found.starts_with(syntax)),
+                "'{cmd}': expected {syntax}, found {found:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn ignores_quoted_shell_syntax() {
+        let quoted = [
+            "cargo test -- --exact 'tests::it works'",
+            "echo '$HOME'",
+            "echo \\$HOME",
+            "grep 'a|b' out.txt",
+            "grep \"a|b\" out.txt",
+            "echo \"a && b; c > d\"",
+            "cat '*.rs'",
+            "cat \"src/?.rs\"",
+            "cargo test --features=serde",
+            "echo \"it's\"",
+        ];
+
+        for cmd in quoted {
+            assert_eq!(shell_syntax(cmd), None, "'{cmd}'");
+        }
+    }
+
+    #[test]
+    fn splits_commands_with_shell_syntax() {
+        assert_eq!(
+            line("echo $HOME 'a b'").argv(false).unwrap(),
+            ["echo", "$HOME", "a b"]
+        );
+        assert!(line("echo $HOME").warning(false).is_some());
+
+        assert_eq!(
+            line("echo $HOME | cat").argv(true).unwrap(),
+            ["sh", "-c", "echo $HOME | cat"]
+        );
+        assert_eq!(line("echo $HOME | cat").warning(true), None);
+    }
+
+    #[test]
+    fn warns_about_shell_with_arguments() {
+        let cmd =
+            JsonCmdV1::Args(vec!["cargo".to_string(), "test".to_string()]);
+
+        assert_eq!(cmd.argv(true).unwrap(), ["cargo", "test"]);
+        assert!(cmd.warning(true).is_some());
+        assert_eq!(cmd.warning(false), None);
+    }
+
+    #[test]
+    fn rejects_invalid_commands() {
+        assert!(matches!(
+            line("echo 'unterminated").argv(false),
+            Err(CmdError::UnterminatedQuote(_))
+        ));
+        assert!(matches!(line("  ").argv(false), Err(CmdError::Empty)));
+        assert!(matches!(
+            JsonCmdV1::Args(vec![]).argv(false),
+            Err(CmdError::Empty)
+        ));
+    }
+}
@@ -1,19 +1,181 @@
+use std::{
+    collections::BTreeMap,
+    path::{Component, Path},
+};
+
+use reqwest::blocking::Client;
 use serde::{Deserialize, Deserializer, Serialize};
 
-use crate::constants::BACKEND_URL;
+use crate::config::Config;
+
+use self::cmd::JsonCmdV1;
 
 use super::{CourseMetaData, JsonCourse, MetadataError};
 
+pub mod cmd;
 pub mod redis;
+pub mod ws;
 
 #[derive(Serialize, Deserialize, Debug, Default)]
 pub struct JsonTestV1 {
     pub name: String,
     pub slug: String,
     pub optional: bool,
-    pub cmd: String,
+    pub cmd: JsonCmdV1,
+    /// Whether `cmd` is run through `sh -c` instead of being split into
+    /// arguments.
+    #[serde(default)]
+    pub shell: bool,
     pub message_on_fail: String,
     pub message_on_success: String,
+    /// Whether this test can run concurrently with neighbouring independent
+    /// tests when using `--jobs`.
+    #[serde(default)]
+    pub independent: bool,
+    #[serde(flatten)]
+    pub limits: JsonLimitsV1,
+    /// Environment variables set for the test process, in addition to those
+    /// of the CLI.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub env: BTreeMap<String, String>,
+    /// Directory the test is run from, relative to the tester workspace.
+    /// Courses with absolute paths, or paths leading out of the workspace,
+    /// here or in `stdin` and `expected_*`, fail to load.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cwd: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stdin: Option<JsonStdinV1>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expected_stdout: Option<JsonExpectedV1>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expected_stderr: Option<JsonExpectedV1>,
+    /// Slugs of the tests or lessons which must pass before this test is run,
+    /// tests of other lessons being written as 'lesson/test' if their slug is
+    /// used in several lessons. Defaults to the previous test, or for independent tests to the last test
+    /// before them which is not independent.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub depends_on: Option<Vec<String>>,
+}
+
+/// Input written to the `stdin` of a test, either inline:
+///
+/// ```yaml
+/// stdin: "3\n4\n"
+/// ```
+///
+/// or read from a file relative to the tester workspace:
+///
+/// ```yaml
+/// stdin:
+///   file: fixtures/input.txt
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum JsonStdinV1 {
+    Inline(String),
+    File { file: String },
+}
+
+/// Output a test is expected to write, either as text:
+///
+/// ```yaml
+/// expected_stdout: "Hello, world!"
+/// ```
+///
+/// as a regular expression which must match part of the output, where `^` and
+/// `$` match at the start and end of each line:
+///
+/// ```yaml
+/// expected_stdout:
+///   regex: "^result: \\d+$"
+/// ```
+///
+/// or as a golden file relative to the tester workspace:
+///
+/// ```yaml
+/// expected_stdout:
+///   file: fixtures/expected.txt
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum JsonExpectedV1 {
+    Exact(String),
+    Regex { regex: String },
+    File { file: String },
+}
+
+impl JsonTestV1 {
+    /// Returns the first regular expression of this test which cannot be
+    /// compiled, along with the reason why.
+    #[cfg(not(debug_assertions))]
+    pub fn regex_invalid(&self) -> Option<(&str, regex::Error)> {
+        [&self.expected_stdout, &self.expected_stderr].into_iter().find_map(
+            |expected| match expected {
+                Some(JsonExpectedV1::Regex { regex }) => {
+                    regex::Regex::new(regex)
+                        .err()
+                        .map(|err| (regex.as_str(), err))
+                }
+                _ => None,
+            },
+        )
+    }
+
+    /// Returns the first path of this test which does not stay within the
+    /// tester workspace, if any.
+    pub fn path_outside_workspace(&self) -> Option<&str> {
+        let cwd = self.cwd.as_deref();
+        let stdin = match &self.stdin {
+            Some(JsonStdinV1::File { file }) => Some(file.as_str()),
+            _ => None,
+        };
+        let [stdout, stderr] = [&self.expected_stdout, &self.expected_stderr]
+            .map(|expected| match expected {
+                Some(JsonExpectedV1::File { file }) => Some(file.as_str()),
+                _ => None,
+            });
+
+        [cwd, stdin, stdout, stderr].into_iter().flatten().find(|path| {
+            !Path::new(path).components().all(|component| {
+                matches!(component, Component::Normal(_) | Component::CurDir)
+            })
+        })
+    }
+}
+
+/// Resource limits applied to a test. These can be set per-test or as a
+/// course-wide default in the tester definition, in which case per-test values
+/// take precedence.
+///
+/// * `timeout`: maximum test duration, in seconds.
+/// * `max_memory`: maximum virtual memory of the test process, in MiB.
+/// * `max_output_bytes`: maximum size of `stdout` and `stderr`, in bytes.
+/// * `max_processes`: maximum number of processes, counted across all
+///   processes of the user, guarding against fork bombs.
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct JsonLimitsV1 {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_memory: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_output_bytes: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_processes: Option<u64>,
+}
+
+impl JsonLimitsV1 {
+    /// Fills in any limit which is not set with the value from `defaults`.
+    pub fn or(&self, defaults: &JsonLimitsV1) -> JsonLimitsV1 {
+        JsonLimitsV1 {
+            timeout: self.timeout.or(defaults.timeout),
+            max_memory: self.max_memory.or(defaults.max_memory),
+            max_output_bytes: self
+                .max_output_bytes
+                .or(defaults.max_output_bytes),
+            max_processes: self.max_processes.or(defaults.max_processes),
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Default)]
@@ -31,6 +193,10 @@ pub struct JsonLessonV1 {
     pub slug: String,
     pub duration: u32,
     pub tests: Option<Vec<JsonTestV1>>,
+    /// Slugs of the tests or lessons which must pass before the first test of
+    /// this lesson is run, unless it sets its own dependencies.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub depends_on: Option<Vec<String>>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Default)]
@@ -47,56 +213,45 @@ pub struct JsonRepoV1 {
     pub commit_sha: String,
 }
 
+/// Body of the request sent to the backend `/submission` endpoint to retrieve
+/// course metadata.
+#[derive(Serialize, Debug)]
+pub struct JsonSubmissionRequestV1<'a> {
+    pub repo_name: &'a str,
+    pub commit_sha: &'a str,
+}
+
 impl JsonRepoV1 {
-    pub fn fetch_metadata(&self) -> Result<CourseMetaData, MetadataError> {
+    pub fn fetch_metadata(
+        &self,
+        client: &Client,
+        config: &Config,
+    ) -> Result<CourseMetaData, MetadataError> {
         let Self { name, commit_sha } = self;
 
-        let request = format!(
-            concat!(
-                "{{",
-                "\"repo_name\":",
-                "\"{}\",",
-                "\"commit_sha\":",
-                "\"{}\"",
-                "}}"
-            ),
-            name, commit_sha
-        );
-
-        log::debug!("fetching metadata: {request}");
-
-        // TODO: use reqwest for fetching data
-        let output = std::process::Command::new("curl")
-            .arg("-fsSL")
-            .arg("-H")
-            .arg("Content-Type: application/json")
-            .arg("-d")
-            .arg(request)
-            .arg(format!("{}/submission", BACKEND_URL))
-            .output()
-            .map(|output| (output.status.success(), output));
-
-        match output {
-            Ok((true, output)) => {
-                log::debug!("extracting course metadata from JSON");
-
-                let metadata =
-                    serde_json::from_slice::<CourseMetaData>(&output.stdout)
-                        .map_err(|e| {
-                            MetadataError::MetadataFmtError(e.to_string())
-                        })?;
-
-                Ok(metadata)
-            }
-            Ok((false, output)) => {
-                let stderr = String::from_utf8(output.stderr).unwrap();
-
-                log::debug!("course metadata retrieval failed: {stderr}");
-
-                Err(MetadataError::MetadataRetrievalError(stderr))
-            }
-            Err(e) => Err(MetadataError::MetadataRetrievalError(e.to_string())),
+        let request = JsonSubmissionRequestV1 { repo_name: name, commit_sha };
+
+        log::debug!("fetching metadata: {request:?}");
+
+        let response = client
+            .post(format!("{}/submission", config.backend_url()))
+            .json(&request)
+            .send()?;
+
+        if !response.status().is_success() {
+            log::debug!(
+                "course metadata retrieval failed, HTTP status: {}",
+                response.status()
+            );
+
+            return Err(MetadataError::Http(response.status()));
         }
+
+        log::debug!("extracting course metadata from JSON");
+
+        let metadata = response.json::<CourseMetaData>()?;
+
+        Ok(metadata)
     }
 }
 
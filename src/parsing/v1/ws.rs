@@ -0,0 +1,147 @@
+//! Events sent to DotCodeSchool over the test results websocket.
+//!
+//! Every message is a single JSON object holding the protocol `version` and an
+//! `event_type`, followed by the fields of that event:
+//!
+//! ```json
+//! {"version":1,"event_type":"init","stream_id":"..."}
+//! {"version":1,"event_type":"log","result":{"slug":"...","output":"...","state":"Passed"}}
+//! {"version":1,"event_type":"status","success":true}
+//! {"version":1,"event_type":"disconnect"}
+//! ```
+//!
+//! The `result` of a `log` event is a [`RedisTestResultV1`]. The wire format is
+//! the same in debug and release builds.
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tungstenite::Message;
+
+use super::redis::RedisTestResultV1;
+
+/// Version of the websocket protocol, bumped on any change to the wire format.
+pub const WS_PROTOCOL_VERSION: u32 = 1;
+
+#[derive(Error, Debug)]
+pub enum WsProtocolError {
+    #[error("failed to encode websocket event: {0}")]
+    WsEncode(String),
+    #[error("failed to decode websocket event: {0}")]
+    WsDecode(String),
+    #[error(
+        "unsupported websocket protocol version {0}, expected {}",
+        WS_PROTOCOL_VERSION
+    )]
+    UnsupportedVersion(u32),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "event_type", rename_all = "snake_case")]
+pub enum WsEvent {
+    /// Opens the log stream the following events are sent to.
+    Init { stream_id: String },
+    /// Result of a single test.
+    Log { result: RedisTestResultV1 },
+    /// Whether all mandatory tests passed, sent once at the end of a run.
+    Status { success: bool },
+    /// Closes the log stream.
+    Disconnect,
+}
+
+/// A [`WsEvent`] along with the version of the protocol it was encoded with.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct WsMessage {
+    pub version: u32,
+    #[serde(flatten)]
+    pub event: WsEvent,
+}
+
+impl WsEvent {
+    /// Encodes this event as a websocket text message, using the current
+    /// protocol version.
+    pub fn encode(self) -> Result<Message, WsProtocolError> {
+        let message = WsMessage { version: WS_PROTOCOL_VERSION, event: self };
+
+        serde_json::to_string(&message)
+            .map(Message::Text)
+            .map_err(|err| WsProtocolError::WsEncode(err.to_string()))
+    }
+
+    /// Decodes an event from a websocket message, rejecting messages which
+    /// were not encoded with the current protocol version. Used to inspect
+    /// what the CLI sends.
+    #[allow(dead_code)]
+    pub fn decode(message: &Message) -> Result<Self, WsProtocolError> {
+        let text = message
+            .to_text()
+            .map_err(|err| WsProtocolError::WsDecode(err.to_string()))?;
+
+        let WsMessage { version, event } = serde_json::from_str(text)
+            .map_err(|err| WsProtocolError::WsDecode(err.to_string()))?;
+
+        if version != WS_PROTOCOL_VERSION {
+            return Err(WsProtocolError::UnsupportedVersion(version));
+        }
+
+        Ok(event)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(event: WsEvent) -> WsEvent {
+        WsEvent::decode(&event.encode().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn log_round_trip() {
+        let pass = RedisTestResultV1::pass("test-1", "ok");
+        let fail = RedisTestResultV1::fail("test-2", "expected 2, got 3", true);
+
+        for result in [pass, fail] {
+            let event = WsEvent::Log { result };
+            assert_eq!(round_trip(event.clone()), event);
+        }
+    }
+
+    #[test]
+    fn events_round_trip() {
+        let events = [
+            WsEvent::Init { stream_id: "stream".to_string() },
+            WsEvent::Status { success: false },
+            WsEvent::Disconnect,
+        ];
+
+        for event in events {
+            assert_eq!(round_trip(event.clone()), event);
+        }
+    }
+
+    #[test]
+    fn log_wire_format() {
+        let result = RedisTestResultV1::pass("test-1", "ok");
+        let Message::Text(text) = WsEvent::Log { result }.encode().unwrap()
+        else {
+            panic!("expected a text message");
+        };
+
+        assert_eq!(
+            text,
+            r#"{"version":1,"event_type":"log","result":{"slug":"test-1","output":"ok","state":"Passed"}}"#
+        );
+    }
+
+    #[test]
+    fn decode_rejects_other_versions() {
+        let message = Message::Text(
+            r#"{"version":2,"event_type":"disconnect"}"#.to_string(),
+        );
+
+        assert!(matches!(
+            WsEvent::decode(&message),
+            Err(WsProtocolError::UnsupportedVersion(2))
+        ));
+    }
+}
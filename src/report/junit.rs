@@ -0,0 +1,133 @@
+//! JUnit XML report. Tests are grouped into one `<testsuite>` per lesson.
+
+use std::io::Write;
+
+use indexmap::IndexMap;
+
+use super::{output_sanitize, TestRecord, TestStatus};
+
+pub fn write<W: Write>(
+    w: &mut W,
+    records: &[TestRecord],
+) -> std::io::Result<()> {
+    let mut suites = IndexMap::<(&str, &str), Vec<&TestRecord>>::new();
+    for record in records {
+        suites
+            .entry((&record.section, &record.lesson))
+            .or_default()
+            .push(record);
+    }
+
+    let (tests, failures, skipped, time) = stats(records.iter());
+
+    writeln!(w, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(
+        w,
+        r#"<testsuites name="dotcodeschool" tests="{tests}" failures="{failures}" skipped="{skipped}" time="{time:.3}">"#
+    )?;
+
+    for ((section, lesson), records) in suites {
+        let (tests, failures, skipped, time) = stats(records.iter().copied());
+        writeln!(
+            w,
+            r#"  <testsuite name="{}" tests="{tests}" failures="{failures}" skipped="{skipped}" time="{time:.3}">"#,
+            escape(&format!("{section}/{lesson}"))
+        )?;
+
+        for record in records {
+            testcase_write(w, record)?;
+        }
+
+        writeln!(w, "  </testsuite>")?;
+    }
+
+    writeln!(w, "</testsuites>")?;
+    w.flush()
+}
+
+fn testcase_write<W: Write>(
+    w: &mut W,
+    record: &TestRecord,
+) -> std::io::Result<()> {
+    writeln!(
+        w,
+        r#"    <testcase name="{}" classname="{}" time="{:.3}">"#,
+        escape(&record.name),
+        escape(&format!("{}.{}", record.section, record.lesson)),
+        record.output.duration.as_secs_f64()
+    )?;
+
+    match record.status {
+        TestStatus::Pass => {}
+        TestStatus::Fail | TestStatus::Timeout if record.optional => {
+            writeln!(
+                w,
+                r#"      <skipped message="optional test failed: {}"/>"#,
+                escape(&record.message)
+            )?;
+        }
+        TestStatus::Fail => {
+            writeln!(
+                w,
+                r#"      <failure message="{}" type="failure"/>"#,
+                escape(&record.message)
+            )?;
+        }
+        TestStatus::Timeout => {
+            writeln!(
+                w,
+                r#"      <failure message="{}" type="timeout"/>"#,
+                escape(&record.message)
+            )?;
+        }
+        TestStatus::Skipped => {
+            writeln!(
+                w,
+                r#"      <skipped message="{}"/>"#,
+                escape(&record.message)
+            )?;
+        }
+    }
+
+    if !record.output.stdout.is_empty() {
+        writeln!(
+            w,
+            "      <system-out>{}</system-out>",
+            escape(&record.output.stdout)
+        )?;
+    }
+    if !record.output.stderr.is_empty() {
+        writeln!(
+            w,
+            "      <system-err>{}</system-err>",
+            escape(&record.output.stderr)
+        )?;
+    }
+
+    writeln!(w, "    </testcase>")
+}
+
+/// Returns the number of tests, failures, skipped tests and total duration
+/// across `records`. Failed optional tests count as skipped.
+fn stats<'a>(
+    records: impl Iterator<Item = &'a TestRecord>,
+) -> (usize, usize, usize, f64) {
+    records.fold((0, 0, 0, 0.0), |(tests, failures, skipped, time), record| {
+        let time = time + record.output.duration.as_secs_f64();
+        match record.status {
+            TestStatus::Pass => (tests + 1, failures, skipped, time),
+            TestStatus::Skipped => (tests + 1, failures, skipped + 1, time),
+            _ if record.optional => (tests + 1, failures, skipped + 1, time),
+            _ => (tests + 1, failures + 1, skipped, time),
+        }
+    })
+}
+
+fn escape(s: &str) -> String {
+    output_sanitize(s)
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
@@ -0,0 +1,229 @@
+//! Test reports in standard formats, for consumption by CI systems.
+//!
+//! Reports are written once a test run completes, independently of whether
+//! results are also sent to DotCodeSchool. Tests which were not run because a
+//! previous mandatory test failed are reported as skipped.
+
+use std::{fs::File, io::BufWriter, path::PathBuf, str::FromStr};
+
+use thiserror::Error;
+
+use crate::{
+    db::{PathLink, TestState},
+    parsing::{TestOutput, TestResult},
+};
+
+pub mod junit;
+pub mod tap;
+
+#[derive(Error, Debug)]
+pub enum ReportError {
+    #[error(
+        "invalid report format '{0}', expected 'junit=<path>', 'tap' or \
+         'tap=<path>'"
+    )]
+    ReportFmt(String),
+    #[error("failed to write report to '{0}': {1}")]
+    ReportWrite(String, String),
+}
+
+/// A report requested by the user with `--report`.
+#[derive(Clone, Debug)]
+pub enum ReportFormat {
+    /// JUnit XML report, written to a file.
+    Junit(PathBuf),
+    /// TAP version 13 report, written to a file or `stdout`.
+    Tap(Option<PathBuf>),
+}
+
+impl FromStr for ReportFormat {
+    type Err = ReportError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once('=') {
+            Some(("junit", path)) if !path.is_empty() => {
+                Ok(ReportFormat::Junit(PathBuf::from(path)))
+            }
+            Some(("tap", path)) if !path.is_empty() => {
+                Ok(ReportFormat::Tap(Some(PathBuf::from(path))))
+            }
+            None if s == "tap" => Ok(ReportFormat::Tap(None)),
+            _ => Err(ReportError::ReportFmt(s.to_string())),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TestStatus {
+    Pass,
+    Fail,
+    Timeout,
+    Skipped,
+}
+
+/// The result of a single test, as it appears in a report.
+#[derive(Debug, Clone)]
+pub struct TestRecord {
+    pub section: String,
+    pub lesson: String,
+    pub name: String,
+    pub slug: String,
+    pub optional: bool,
+    pub status: TestStatus,
+    pub message: String,
+    pub output: TestOutput,
+}
+
+impl TestRecord {
+    pub fn new(test: &TestState, result: &TestResult) -> Self {
+        let (status, message) = match result {
+            TestResult::Pass(_) => {
+                (TestStatus::Pass, test.message_on_success.clone())
+            }
+            TestResult::Fail(_) => {
+                (TestStatus::Fail, test.message_on_fail.clone())
+            }
+            TestResult::Timeout(_, timeout) => (
+                TestStatus::Timeout,
+                format!("timed out after {}s", timeout.as_secs()),
+            ),
+        };
+
+        Self {
+            status,
+            message,
+            output: result.output().clone(),
+            ..Self::skipped(test)
+        }
+    }
+
+    /// Record for a test which was not run.
+    pub fn skipped(test: &TestState) -> Self {
+        let link_name = |link: Option<&PathLink>| match link {
+            Some(PathLink::Link(name) | PathLink::LinkOptional(name)) => {
+                name.clone()
+            }
+            None => String::default(),
+        };
+
+        Self {
+            section: link_name(test.path.first()),
+            lesson: link_name(test.path.get(1)),
+            name: test.name.clone(),
+            slug: test.slug.clone(),
+            optional: test.optional,
+            status: TestStatus::Skipped,
+            message: "not run".to_string(),
+            output: TestOutput::default(),
+        }
+    }
+
+    /// `section/lesson/test` path to this test.
+    pub fn path(&self) -> String {
+        format!("{}/{}/{}", self.section, self.lesson, self.name)
+    }
+}
+
+/// Collects test results over a run and writes them to every requested
+/// report format once the run is over.
+pub struct Report {
+    formats: Vec<ReportFormat>,
+    records: Vec<TestRecord>,
+}
+
+impl Report {
+    pub fn new(formats: Vec<ReportFormat>) -> Self {
+        Self { formats, records: vec![] }
+    }
+
+    pub fn record(&mut self, test: &TestState, result: &TestResult) {
+        if !self.formats.is_empty() {
+            self.records.push(TestRecord::new(test, result));
+        }
+    }
+
+    /// Records a test which was not run as one of its dependencies failed.
+    pub fn record_blocked(&mut self, test: &TestState, blocker: &str) {
+        if !self.formats.is_empty() {
+            self.records.push(TestRecord {
+                message: format!("blocked by {blocker}"),
+                ..TestRecord::skipped(test)
+            });
+        }
+    }
+
+    /// Writes all reports. Tests in `tests` which do not have a result yet are
+    /// reported as skipped.
+    pub fn finish(
+        &mut self,
+        tests: &[(sled::IVec, TestState)],
+    ) -> Result<(), ReportError> {
+        if self.formats.is_empty() {
+            return Ok(());
+        }
+
+        let skipped = tests
+            .iter()
+            .skip(self.records.len())
+            .map(|(_, test)| TestRecord::skipped(test))
+            .collect::<Vec<_>>();
+        self.records.extend(skipped);
+
+        for format in self.formats.iter() {
+            log::debug!("writing report: {format:?}");
+
+            let (path, result) = match format {
+                ReportFormat::Junit(path) => (
+                    path.display().to_string(),
+                    File::create(path).and_then(|file| {
+                        junit::write(&mut BufWriter::new(file), &self.records)
+                    }),
+                ),
+                ReportFormat::Tap(Some(path)) => (
+                    path.display().to_string(),
+                    File::create(path).and_then(|file| {
+                        tap::write(&mut BufWriter::new(file), &self.records)
+                    }),
+                ),
+                ReportFormat::Tap(None) => (
+                    "stdout".to_string(),
+                    tap::write(&mut std::io::stdout().lock(), &self.records),
+                ),
+            };
+
+            result.map_err(|err| {
+                ReportError::ReportWrite(path, err.to_string())
+            })?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Removes terminal escape sequences and other control characters from test
+/// output, which would otherwise produce invalid reports.
+pub(crate) fn output_sanitize(output: &str) -> String {
+    let mut sanitized = String::with_capacity(output.len());
+    let mut chars = output.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            // ANSI escape sequence: ESC [ ... final byte
+            '\u{1b}' => {
+                if chars.peek() == Some(&'[') {
+                    chars.next();
+                    for c in chars.by_ref() {
+                        if ('@'..='~').contains(&c) {
+                            break;
+                        }
+                    }
+                }
+            }
+            '\n' | '\t' => sanitized.push(c),
+            c if c.is_control() => {}
+            c => sanitized.push(c),
+        }
+    }
+
+    sanitized
+}
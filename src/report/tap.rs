@@ -0,0 +1,74 @@
+//! TAP version 13 report. Failure details and test output are included as
+//! YAML diagnostic blocks.
+
+use std::io::Write;
+
+use super::{output_sanitize, TestRecord, TestStatus};
+
+pub fn write<W: Write>(
+    w: &mut W,
+    records: &[TestRecord],
+) -> std::io::Result<()> {
+    writeln!(w, "TAP version 13")?;
+    writeln!(w, "1..{}", records.len())?;
+
+    for (i, record) in records.iter().enumerate() {
+        let description = record.path().replace('#', "\\#");
+        let n = i + 1;
+
+        match record.status {
+            TestStatus::Pass => {
+                writeln!(w, "ok {n} - {description}")?;
+            }
+            TestStatus::Skipped => {
+                writeln!(
+                    w,
+                    "ok {n} - {description} # SKIP {}",
+                    record.message
+                )?;
+            }
+            TestStatus::Fail | TestStatus::Timeout if record.optional => {
+                writeln!(w, "not ok {n} - {description} # TODO optional")?;
+            }
+            TestStatus::Fail | TestStatus::Timeout => {
+                writeln!(w, "not ok {n} - {description}")?;
+            }
+        }
+
+        if record.status != TestStatus::Skipped {
+            diagnostics_write(w, record)?;
+        }
+    }
+
+    w.flush()
+}
+
+fn diagnostics_write<W: Write>(
+    w: &mut W,
+    record: &TestRecord,
+) -> std::io::Result<()> {
+    writeln!(w, "  ---")?;
+    writeln!(w, "  slug: '{}'", record.slug.replace('\'', "''"))?;
+    writeln!(w, "  message: '{}'", record.message.replace('\'', "''"))?;
+    writeln!(w, "  duration_ms: {}", record.output.duration.as_millis())?;
+    block_write(w, "stdout", &record.output.stdout)?;
+    block_write(w, "stderr", &record.output.stderr)?;
+    writeln!(w, "  ...")
+}
+
+fn block_write<W: Write>(
+    w: &mut W,
+    key: &str,
+    content: &str,
+) -> std::io::Result<()> {
+    if content.trim().is_empty() {
+        return Ok(());
+    }
+
+    writeln!(w, "  {key}: |-")?;
+    for line in output_sanitize(content).lines() {
+        writeln!(w, "    {line}")?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,56 @@
+//! JUnit XML and TAP reports requested with `--report`.
+
+use crate::{
+    db::TestState,
+    parsing::TestResult,
+    report::{Report, ReportFormat},
+};
+
+use super::{Reporter, ReporterError, RunStatus};
+
+pub struct FileReporter {
+    report: Report,
+    tests: Vec<(sled::IVec, TestState)>,
+}
+
+impl FileReporter {
+    pub fn new(formats: Vec<ReportFormat>) -> Self {
+        Self { report: Report::new(formats), tests: vec![] }
+    }
+}
+
+impl Reporter for FileReporter {
+    fn run_start(
+        &mut self,
+        tests: &[(sled::IVec, TestState)],
+    ) -> Result<(), ReporterError> {
+        self.tests = tests.to_vec();
+
+        Ok(())
+    }
+
+    fn test_result(
+        &mut self,
+        _index: usize,
+        test: &TestState,
+        result: &TestResult,
+    ) -> Result<(), ReporterError> {
+        self.report.record(test, result);
+
+        Ok(())
+    }
+
+    fn test_blocked(
+        &mut self,
+        _index: usize,
+        test: &TestState,
+        blocker: &str,
+    ) -> Result<(), ReporterError> {
+        self.report.record_blocked(test, blocker);
+        Ok(())
+    }
+
+    fn run_status(&mut self, _status: &RunStatus) -> Result<(), ReporterError> {
+        Ok(self.report.finish(&self.tests)?)
+    }
+}
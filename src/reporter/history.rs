@@ -0,0 +1,49 @@
+//! Records each run in the history browsed with `dotcodeschool history`.
+
+use crate::{
+    db::TestState,
+    history::{History, RunRecord, TestRecord},
+    parsing::TestResult,
+};
+
+use super::{Reporter, ReporterError, RunStatus};
+
+pub struct HistoryReporter {
+    history: History,
+    run: RunRecord,
+}
+
+impl HistoryReporter {
+    pub fn new(history: History, commit: Option<String>) -> Self {
+        Self { history, run: RunRecord::new(commit) }
+    }
+}
+
+impl Reporter for HistoryReporter {
+    fn test_result(
+        &mut self,
+        _index: usize,
+        test: &TestState,
+        result: &TestResult,
+    ) -> Result<(), ReporterError> {
+        self.run.tests.push(TestRecord::new(test, result));
+        Ok(())
+    }
+
+    fn test_blocked(
+        &mut self,
+        _index: usize,
+        test: &TestState,
+        _blocker: &str,
+    ) -> Result<(), ReporterError> {
+        self.run.tests.push(TestRecord::blocked(test));
+        Ok(())
+    }
+
+    fn run_status(&mut self, status: &RunStatus) -> Result<(), ReporterError> {
+        self.run.success = status.success;
+        self.history.push(&self.run)?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,61 @@
+//! Newline-delimited JSON events, used with `--format json`.
+
+use crate::{
+    db::TestState,
+    event::{self, Event},
+    parsing::TestResult,
+};
+
+use super::{Reporter, ReporterError, RunStatus};
+
+pub struct JsonReporter;
+
+impl Reporter for JsonReporter {
+    fn test_start(
+        &mut self,
+        index: usize,
+        test: &TestState,
+    ) -> Result<(), ReporterError> {
+        event::emit(Event::test_started(index, test));
+
+        Ok(())
+    }
+
+    fn test_result(
+        &mut self,
+        index: usize,
+        test: &TestState,
+        result: &TestResult,
+    ) -> Result<(), ReporterError> {
+        event::emit(Event::test_finished(index, test, result));
+
+        Ok(())
+    }
+
+    fn test_blocked(
+        &mut self,
+        index: usize,
+        test: &TestState,
+        blocker: &str,
+    ) -> Result<(), ReporterError> {
+        event::emit(Event::TestBlocked {
+            index,
+            path: test.path_to(),
+            slug: test.slug.clone(),
+            blocked_by: blocker.to_string(),
+        });
+        Ok(())
+    }
+
+    fn run_status(&mut self, status: &RunStatus) -> Result<(), ReporterError> {
+        event::emit(Event::Summary {
+            success: status.success,
+            passed: status.passed,
+            total: status.total,
+            score: status.score(),
+            reason: status.reason.clone(),
+        });
+
+        Ok(())
+    }
+}
@@ -0,0 +1,202 @@
+//! Destinations test results are reported to while tests run.
+//!
+//! [`RunnerV1`](crate::runner::v1::RunnerV1) only knows about the
+//! [`Reporter`] trait. The [`Monitor`](crate::monitor::Monitor) decides which
+//! reporters are used for a run and composes them with [`Reporters`].
+
+use thiserror::Error;
+
+use crate::{
+    db::{DbError, TestState},
+    parsing::{v1::ws::WsProtocolError, TestResult},
+    report::ReportError,
+};
+
+pub mod file;
+pub mod history;
+pub mod json;
+pub mod outbox;
+pub mod terminal;
+pub mod test_log;
+pub mod ws;
+
+#[derive(Error, Debug)]
+pub enum ReporterError {
+    #[error("{0}")]
+    WsProtocolError(#[from] WsProtocolError),
+    #[error("failed to send report via websocket: {0}")]
+    WsError(String),
+    #[error(
+        "could not reach DotCodeSchool, {0} events will be sent on the next \
+         run or with `dotcodeschool sync`"
+    )]
+    WsQueued(usize),
+    #[error("{0}")]
+    DbError(#[from] DbError),
+    #[error("{0}")]
+    ReportError(#[from] ReportError),
+    #[error("{}", .0.iter().map(ToString::to_string).collect::<Vec<_>>().join("\n"))]
+    Multiple(Vec<ReporterError>),
+}
+
+/// Outcome of a test run, once all tests have been run or a mandatory test has
+/// failed.
+///
+/// * `passed`: number of tests which passed.
+/// * `total`: number of tests in the run.
+/// * `reason`: why the run failed, if it did.
+#[derive(Debug, Clone)]
+pub struct RunStatus {
+    pub success: bool,
+    pub passed: u32,
+    pub total: usize,
+    pub reason: Option<String>,
+}
+
+impl RunStatus {
+    /// Percentage of tests which passed.
+    pub fn score(&self) -> f64 {
+        if self.total == 0 {
+            return 0.0;
+        }
+        self.passed as f64 / self.total as f64 * 100f64
+    }
+}
+
+/// Receives test results as they become available.
+///
+/// Hooks are called in the following order: [`run_start`] once, then
+/// [`test_start`] and [`test_result`] for each test, or [`test_blocked`] if a
+/// dependency of the test failed, then [`run_status`] and [`close`] once.
+///
+/// Errors returned by reporters are displayed as warnings and never affect the
+/// outcome of a run.
+///
+/// [`run_start`]: Reporter::run_start
+/// [`test_start`]: Reporter::test_start
+/// [`test_result`]: Reporter::test_result
+/// [`test_blocked`]: Reporter::test_blocked
+/// [`run_status`]: Reporter::run_status
+/// [`close`]: Reporter::close
+pub trait Reporter {
+    fn run_start(
+        &mut self,
+        _tests: &[(sled::IVec, TestState)],
+    ) -> Result<(), ReporterError> {
+        Ok(())
+    }
+
+    fn test_start(
+        &mut self,
+        _index: usize,
+        _test: &TestState,
+    ) -> Result<(), ReporterError> {
+        Ok(())
+    }
+
+    fn test_result(
+        &mut self,
+        index: usize,
+        test: &TestState,
+        result: &TestResult,
+    ) -> Result<(), ReporterError>;
+
+    /// `test` was not run as one of its dependencies failed. `blocker` is the
+    /// name of the failing test.
+    fn test_blocked(
+        &mut self,
+        _index: usize,
+        _test: &TestState,
+        _blocker: &str,
+    ) -> Result<(), ReporterError> {
+        Ok(())
+    }
+
+    fn run_status(&mut self, status: &RunStatus) -> Result<(), ReporterError>;
+
+    fn close(&mut self) -> Result<(), ReporterError> {
+        Ok(())
+    }
+}
+
+/// Forwards every hook to several reporters, in the order they were added.
+///
+/// Each reporter is always called, even if a previous one failed. Errors are
+/// combined into [`ReporterError::Multiple`].
+#[derive(Default)]
+pub struct Reporters {
+    reporters: Vec<Box<dyn Reporter>>,
+}
+
+impl Reporters {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push<R>(&mut self, reporter: R)
+    where
+        R: Reporter + 'static,
+    {
+        self.reporters.push(Box::new(reporter));
+    }
+
+    fn for_each<F>(&mut self, mut f: F) -> Result<(), ReporterError>
+    where
+        F: FnMut(&mut dyn Reporter) -> Result<(), ReporterError>,
+    {
+        let mut errors = self
+            .reporters
+            .iter_mut()
+            .filter_map(|reporter| f(reporter.as_mut()).err())
+            .collect::<Vec<_>>();
+
+        match errors.len() {
+            0 => Ok(()),
+            1 => Err(errors.remove(0)),
+            _ => Err(ReporterError::Multiple(errors)),
+        }
+    }
+}
+
+impl Reporter for Reporters {
+    fn run_start(
+        &mut self,
+        tests: &[(sled::IVec, TestState)],
+    ) -> Result<(), ReporterError> {
+        self.for_each(|reporter| reporter.run_start(tests))
+    }
+
+    fn test_start(
+        &mut self,
+        index: usize,
+        test: &TestState,
+    ) -> Result<(), ReporterError> {
+        self.for_each(|reporter| reporter.test_start(index, test))
+    }
+
+    fn test_result(
+        &mut self,
+        index: usize,
+        test: &TestState,
+        result: &TestResult,
+    ) -> Result<(), ReporterError> {
+        self.for_each(|reporter| reporter.test_result(index, test, result))
+    }
+
+    fn test_blocked(
+        &mut self,
+        index: usize,
+        test: &TestState,
+        blocker: &str,
+    ) -> Result<(), ReporterError> {
+        self.for_each(|reporter| reporter.test_blocked(index, test, blocker))
+    }
+
+    fn run_status(&mut self, status: &RunStatus) -> Result<(), ReporterError> {
+        self.for_each(|reporter| reporter.run_status(status))
+    }
+
+    fn close(&mut self) -> Result<(), ReporterError> {
+        self.for_each(|reporter| reporter.close())
+    }
+}
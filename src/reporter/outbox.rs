@@ -0,0 +1,189 @@
+//! Durable queue of websocket events which could not be delivered.
+//!
+//! Events are stored in their own tree of the sled database, in the order they
+//! were sent, along with the log stream they belong to. They are flushed at the
+//! start of the next run which can reach DotCodeSchool, or with
+//! `dotcodeschool sync`.
+
+use std::{net::TcpStream, time::Duration};
+
+use parity_scale_codec::{Decode, Encode};
+use tungstenite::{stream::MaybeTlsStream, Message, WebSocket};
+
+use crate::{
+    db::{DbError, TREE_OUTBOX},
+    parsing::v1::ws::WsEvent,
+};
+
+use super::ReporterError;
+
+/// Number of times a connection is attempted before giving up.
+const RETRY_ATTEMPTS: u32 = 3;
+/// Delay before the first retry, doubled after each attempt.
+const RETRY_BACKOFF: Duration = Duration::from_millis(250);
+
+#[derive(Encode, Decode, Debug, Clone)]
+struct OutboxEntry {
+    ws_url: String,
+    stream_id: String,
+    /// Encoded websocket message, as it would have been sent.
+    message: String,
+}
+
+#[derive(Clone)]
+pub struct Outbox {
+    tree: sled::Tree,
+}
+
+impl Outbox {
+    pub fn open(db: &sled::Db) -> Result<Self, DbError> {
+        let tree = db.open_tree(TREE_OUTBOX).map_err(|err| {
+            DbError::DbOpenTree(TREE_OUTBOX.to_string(), err.to_string())
+        })?;
+
+        Ok(Self { tree })
+    }
+
+    pub fn len(&self) -> usize {
+        self.tree.len()
+    }
+
+    /// Queues `message` for delivery to the log stream `stream_id`.
+    pub fn push(
+        &self,
+        ws_url: &str,
+        stream_id: &str,
+        message: String,
+    ) -> Result<(), DbError> {
+        // Keys are big-endian so that entries are iterated in insertion order
+        let id = match self.tree.last() {
+            Ok(Some((key, _))) => {
+                u64::from_be_bytes(key[..].try_into().unwrap_or_default()) + 1
+            }
+            _ => 0,
+        };
+
+        let entry = OutboxEntry {
+            ws_url: ws_url.to_string(),
+            stream_id: stream_id.to_string(),
+            message,
+        };
+
+        log::debug!("queuing websocket event {id}: {entry:?}");
+
+        self.tree.insert(id.to_be_bytes(), entry.encode()).map_err(|err| {
+            DbError::DbInsert(hex::encode(id.to_be_bytes()), err.to_string())
+        })?;
+
+        Ok(())
+    }
+
+    /// Sends every queued event, in order. Events are removed from the outbox
+    /// as soon as they are delivered, so a failed flush can be resumed later.
+    ///
+    /// Returns the number of events which were delivered.
+    pub fn flush(&self) -> Result<usize, ReporterError> {
+        let mut sent = 0;
+        let mut stream: Option<(String, String, WebSocket<_>)> = None;
+
+        for item in self.tree.iter() {
+            let (key, bytes) = item.map_err(|err| {
+                DbError::DbGet(TREE_OUTBOX.to_string(), err.to_string())
+            })?;
+
+            // Entries which cannot be decoded would block the outbox forever
+            let entry = match OutboxEntry::decode(&mut &bytes[..]) {
+                Ok(entry) => entry,
+                Err(err) => {
+                    log::debug!(
+                        "dropping websocket event {}: {err}",
+                        hex::encode(&key)
+                    );
+                    self.tree.remove(&key).map_err(|err| {
+                        DbError::DbInsert(hex::encode(&key), err.to_string())
+                    })?;
+                    continue;
+                }
+            };
+
+            // Events are sent over one connection per log stream
+            let client = match &mut stream {
+                Some((ws_url, stream_id, client))
+                    if *ws_url == entry.ws_url
+                        && *stream_id == entry.stream_id =>
+                {
+                    client
+                }
+                _ => {
+                    if let Some((_, _, mut client)) = stream.take() {
+                        let _ = client.close(None);
+                    }
+
+                    let client = ws_connect(&entry.ws_url, &entry.stream_id)?;
+                    let (_, _, client) = stream.insert((
+                        entry.ws_url.clone(),
+                        entry.stream_id.clone(),
+                        client,
+                    ));
+                    client
+                }
+            };
+
+            client
+                .send(Message::Text(entry.message))
+                .map_err(|err| ReporterError::WsError(err.to_string()))?;
+
+            self.tree.remove(&key).map_err(|err| {
+                DbError::DbInsert(hex::encode(&key), err.to_string())
+            })?;
+
+            sent += 1;
+        }
+
+        if let Some((_, _, mut client)) = stream {
+            let _ = client.close(None);
+        }
+
+        let _ = self.tree.flush();
+
+        Ok(sent)
+    }
+}
+
+/// Connects to the websocket at `ws_url` and opens the log stream `stream_id`,
+/// retrying with exponential backoff.
+pub fn ws_connect(
+    ws_url: &str,
+    stream_id: &str,
+) -> Result<WebSocket<MaybeTlsStream<TcpStream>>, ReporterError> {
+    let mut backoff = RETRY_BACKOFF;
+    let mut attempt = 1;
+
+    loop {
+        let result = tungstenite::client::connect(ws_url)
+            .map_err(|err| ReporterError::WsError(err.to_string()))
+            .and_then(|(mut client, _)| {
+                let init = WsEvent::Init { stream_id: stream_id.to_string() }
+                    .encode()?;
+                client
+                    .send(init)
+                    .map_err(|err| ReporterError::WsError(err.to_string()))?;
+                Ok(client)
+            });
+
+        match result {
+            Ok(client) => return Ok(client),
+            Err(err) if attempt >= RETRY_ATTEMPTS => return Err(err),
+            Err(err) => {
+                log::debug!(
+                    "websocket connection attempt {attempt} failed: {err}, \
+                     retrying in {}ms",
+                    backoff.as_millis()
+                );
+                std::thread::sleep(backoff);
+                backoff *= 2;
+                attempt += 1;
+            }
+        }
+    }
+}
@@ -0,0 +1,89 @@
+//! Human-readable output, displayed above the progress bar.
+
+use colored::Colorize;
+use indexmap::IndexMap;
+use indicatif::ProgressBar;
+
+use crate::{db::TestState, parsing::TestResult, runner::format_result};
+
+use super::{Reporter, ReporterError, RunStatus};
+
+pub struct TerminalReporter {
+    progress: ProgressBar,
+    /// Number of tests which were not run, by name of the failing test which
+    /// blocked them.
+    blocked: IndexMap<String, usize>,
+}
+
+impl TerminalReporter {
+    pub fn new(progress: ProgressBar) -> Self {
+        Self { progress, blocked: IndexMap::new() }
+    }
+}
+
+impl Reporter for TerminalReporter {
+    fn run_start(
+        &mut self,
+        tests: &[(sled::IVec, TestState)],
+    ) -> Result<(), ReporterError> {
+        self.progress.println(format!(
+            "\n📒 You have {} exercises left",
+            tests.len().to_string().bold()
+        ));
+
+        Ok(())
+    }
+
+    fn test_start(
+        &mut self,
+        _index: usize,
+        test: &TestState,
+    ) -> Result<(), ReporterError> {
+        self.progress.println(format!("{test}"));
+
+        Ok(())
+    }
+
+    fn test_result(
+        &mut self,
+        _index: usize,
+        test: &TestState,
+        result: &TestResult,
+    ) -> Result<(), ReporterError> {
+        self.progress.println(format_result(test, result));
+
+        Ok(())
+    }
+
+    fn test_blocked(
+        &mut self,
+        _index: usize,
+        _test: &TestState,
+        blocker: &str,
+    ) -> Result<(), ReporterError> {
+        *self.blocked.entry(blocker.to_string()).or_default() += 1;
+        Ok(())
+    }
+
+    fn run_status(&mut self, status: &RunStatus) -> Result<(), ReporterError> {
+        for (blocker, count) in self.blocked.iter() {
+            let plural = if *count == 1 { "" } else { "s" };
+            self.progress.println(format!(
+                "\n⛔ {count} test{plural} blocked by {}",
+                blocker.yellow().bold()
+            ));
+        }
+
+        match &status.reason {
+            Some(reason) => self
+                .progress
+                .println(format!("\n⚠ Error: {}", reason.red().bold())),
+            None => self.progress.println(format!(
+                "\n🏁 final score: {}%",
+                format!("{:.2}", status.score()).green().bold()
+            )),
+        }
+
+        Ok(())
+    }
+}
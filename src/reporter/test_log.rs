@@ -0,0 +1,86 @@
+//! Per-test log entries, posted to the DotCodeSchool `/test-log` endpoint.
+
+use reqwest::{blocking::Client, StatusCode};
+
+use crate::{
+    db::{PathLink, TestState},
+    models::TestLogEntry,
+    parsing::TestResult,
+};
+
+use super::{Reporter, ReporterError, RunStatus};
+
+/// Failing to log a test is not considered an error, as the test result has
+/// already been sent over the websocket.
+pub struct TestLogReporter {
+    client: Client,
+    backend_url: String,
+    repo_name: String,
+}
+
+impl TestLogReporter {
+    pub fn new(client: Client, backend_url: String, repo_name: String) -> Self {
+        Self { client, backend_url, repo_name }
+    }
+}
+
+impl Reporter for TestLogReporter {
+    fn test_result(
+        &mut self,
+        _index: usize,
+        test: &TestState,
+        result: &TestResult,
+    ) -> Result<(), ReporterError> {
+        // Get path info from test state
+        let [section_link, lesson_link, _, _] = &test.path[..] else {
+            return Ok(());
+        };
+
+        // Extract section and lesson names
+        let section_name = match section_link {
+            PathLink::Link(name) | PathLink::LinkOptional(name) => name.clone(),
+        };
+
+        let lesson_name = match lesson_link {
+            PathLink::Link(name) | PathLink::LinkOptional(name) => name.clone(),
+        };
+
+        // Use the lesson slug directly from the test state
+        // This is the slug defined in the tester-definition.yml file
+        let lesson_slug = test.lesson_slug.clone();
+
+        let test_log = TestLogEntry {
+            test_slug: test.slug.clone(),
+            passed: matches!(result, TestResult::Pass(_)),
+            timestamp: chrono::Utc::now(),
+            section_name,
+            lesson_name,
+            lesson_slug,
+            test_name: test.name.clone(),
+            repo_name: self.repo_name.clone(),
+        };
+
+        let url = format!("{}/test-log", self.backend_url);
+        match self.client.post(&url).json(&test_log).send() {
+            Ok(response) => {
+                if response.status() == StatusCode::OK {
+                    log::info!("Test log entry sent successfully");
+                } else {
+                    log::error!(
+                        "Failed to send test log entry: {}",
+                        response.status()
+                    );
+                }
+            }
+            Err(err) => {
+                log::error!("Failed to send test log entry: {}", err);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn run_status(&mut self, _status: &RunStatus) -> Result<(), ReporterError> {
+        Ok(())
+    }
+}
@@ -0,0 +1,161 @@
+//! Live test results streamed to DotCodeSchool over a websocket.
+//!
+//! Reporting over the network must never get in the way of running tests. If
+//! the websocket cannot be reached, even after reconnecting, events are queued
+//! in the [`Outbox`] instead and delivered on a later run.
+
+use std::net::TcpStream;
+
+use tungstenite::{stream::MaybeTlsStream, Message, WebSocket};
+
+use crate::{
+    db::TestState,
+    parsing::{
+        v1::{redis::RedisTestResultV1, ws::WsEvent},
+        TestResult,
+    },
+    runner::format_result,
+};
+
+use super::{
+    outbox::{ws_connect, Outbox},
+    Reporter, ReporterError, RunStatus,
+};
+
+pub struct WsReporter {
+    ws_url: String,
+    stream_id: String,
+    client: Option<WebSocket<MaybeTlsStream<TcpStream>>>,
+    outbox: Outbox,
+    /// Set once DotCodeSchool could not be reached during this run, after
+    /// which events are queued directly.
+    offline: bool,
+    /// Number of events queued during this run.
+    queued: usize,
+}
+
+impl WsReporter {
+    /// Connects to the log stream `stream_id`, first delivering any events
+    /// left over from previous runs.
+    pub fn new(ws_url: String, stream_id: String, outbox: Outbox) -> Self {
+        match outbox.flush() {
+            Ok(0) => {}
+            Ok(sent) => log::info!("delivered {sent} queued websocket events"),
+            Err(err) => log::error!("failed to deliver queued events: {err}"),
+        }
+
+        let mut reporter = Self {
+            ws_url,
+            stream_id,
+            client: None,
+            outbox,
+            offline: false,
+            queued: 0,
+        };
+        reporter.connect();
+
+        reporter
+    }
+
+    fn connect(&mut self) -> bool {
+        match ws_connect(&self.ws_url, &self.stream_id) {
+            Ok(client) => {
+                self.client = Some(client);
+                true
+            }
+            Err(err) => {
+                log::error!("failed to connect to '{}': {err}", self.ws_url);
+                self.offline = true;
+                false
+            }
+        }
+    }
+
+    /// Sends `event`, queuing it in the outbox if DotCodeSchool cannot be
+    /// reached.
+    fn send(&mut self, event: WsEvent) -> Result<(), ReporterError> {
+        let message = event.encode()?;
+
+        log::debug!("Sending message to redis: {message}");
+
+        if self.try_send(&message) {
+            log::debug!("Message sent successfully");
+            return Ok(());
+        }
+
+        let Message::Text(text) = message else {
+            unreachable!("websocket events are encoded as text");
+        };
+
+        self.queued += 1;
+        self.outbox.push(&self.ws_url, &self.stream_id, text)?;
+
+        Ok(())
+    }
+
+    /// Sends `message`, reconnecting once if the connection was dropped.
+    fn try_send(&mut self, message: &Message) -> bool {
+        for _ in 0..2 {
+            if self.client.is_none() && (self.offline || !self.connect()) {
+                return false;
+            }
+
+            let Some(client) = self.client.as_mut() else {
+                return false;
+            };
+
+            match client.send(message.clone()) {
+                Ok(()) => return true,
+                Err(err) => {
+                    log::error!("websocket connection dropped: {err}");
+                    self.client = None;
+                }
+            }
+        }
+
+        false
+    }
+}
+
+impl Reporter for WsReporter {
+    fn test_result(
+        &mut self,
+        _index: usize,
+        test: &TestState,
+        result: &TestResult,
+    ) -> Result<(), ReporterError> {
+        let output = format_result(test, result);
+        let result = match result {
+            TestResult::Pass(_) => RedisTestResultV1::pass(&test.slug, &output),
+            TestResult::Fail(_) | TestResult::Timeout(..) => {
+                RedisTestResultV1::fail(&test.slug, &output, test.optional)
+            }
+        };
+
+        log::debug!("Test result: {result:?}");
+
+        self.send(WsEvent::Log { result })
+    }
+
+    fn run_status(&mut self, status: &RunStatus) -> Result<(), ReporterError> {
+        self.send(WsEvent::Status { success: status.success })
+    }
+
+    fn close(&mut self) -> Result<(), ReporterError> {
+        log::debug!("Closing websocket connection");
+
+        self.send(WsEvent::Disconnect)?;
+
+        if let Some(mut client) = self.client.take() {
+            let _ = client.close(None);
+        }
+
+        if self.queued > 0 {
+            return Err(ReporterError::WsQueued(self.queued));
+        }
+
+        log::debug!("Websocket connection closed successfully");
+
+        Ok(())
+    }
+}
@@ -0,0 +1,220 @@
+//! Assertions on the output of a test.
+//!
+//! By default, a test passes if its command exits successfully. Tests can also
+//! declare the output they are expected to write to `stdout` or `stderr`, as
+//! text, as a regular expression or as a golden file. Trailing newlines are
+//! ignored when comparing text, and regular expressions are matched in
+//! multi-line mode.
+
+use std::path::Path;
+
+use colored::Colorize;
+use regex::RegexBuilder;
+use similar::{ChangeTag, TextDiff};
+
+use crate::db::TestExpected;
+
+/// Number of unchanged lines displayed around each change in a diff.
+const DIFF_CONTEXT: usize = 3;
+
+#[derive(Debug, Clone)]
+pub enum OutputMismatch {
+    /// Output differs from the expected text.
+    Text { stream: &'static str, expected: String, actual: String },
+    /// Output does not match the expected regular expression.
+    Regex { stream: &'static str, pattern: String },
+    /// Expected output could not be loaded.
+    Invalid { stream: &'static str, reason: String },
+}
+
+/// Checks the `actual` output written by a test to `stream` against what is
+/// `expected`. Golden files are read from the `target` workspace.
+pub fn output_check(
+    stream: &'static str,
+    expected: &TestExpected,
+    actual: &str,
+    target: &str,
+) -> Option<OutputMismatch> {
+    let expected = match expected {
+        TestExpected::Exact(text) => text.clone(),
+        TestExpected::File(file) => {
+            match std::fs::read_to_string(Path::new(target).join(file)) {
+                Ok(text) => text,
+                Err(err) => {
+                    return Some(OutputMismatch::Invalid {
+                        stream,
+                        reason: format!(
+                            "could not read expected {stream} from '{file}': \
+                             {err}"
+                        ),
+                    })
+                }
+            }
+        }
+        TestExpected::Regex(pattern) => {
+            // `^` and `$` match at the start and end of each line, as output
+            // usually ends with a newline
+            return match RegexBuilder::new(pattern).multi_line(true).build() {
+                Ok(regex) if regex.is_match(actual) => None,
+                Ok(_) => Some(OutputMismatch::Regex {
+                    stream,
+                    pattern: pattern.clone(),
+                }),
+                Err(err) => Some(OutputMismatch::Invalid {
+                    stream,
+                    reason: format!("invalid regex '{pattern}': {err}"),
+                }),
+            };
+        }
+    };
+
+    let expected = expected.trim_end_matches('\n');
+    let actual = actual.trim_end_matches('\n');
+
+    if expected == actual {
+        None
+    } else {
+        Some(OutputMismatch::Text {
+            stream,
+            expected: expected.to_string(),
+            actual: actual.to_string(),
+        })
+    }
+}
+
+impl OutputMismatch {
+    pub fn title(&self) -> String {
+        match self {
+            Self::Text { stream, .. } => format!("expected {stream}"),
+            Self::Regex { stream, .. } => format!("expected {stream} to match"),
+            Self::Invalid { stream, .. } => format!("expected {stream}"),
+        }
+    }
+
+    /// Lines describing the mismatch. For text, this is a unified diff from
+    /// the expected to the actual output, coloured if `color` is set.
+    pub fn lines(&self, color: bool) -> Vec<String> {
+        match self {
+            Self::Text { expected, actual, .. } => {
+                diff_lines(expected, actual, color)
+            }
+            Self::Regex { pattern, .. } => vec![format!("/{pattern}/")],
+            Self::Invalid { reason, .. } => vec![reason.clone()],
+        }
+    }
+}
+
+fn diff_lines(expected: &str, actual: &str, color: bool) -> Vec<String> {
+    // Both sides end with a newline so that the last line is not reported as
+    // changed because of a missing newline
+    let expected = format!("{expected}\n");
+    let actual = format!("{actual}\n");
+
+    let diff = TextDiff::from_lines(&expected, &actual);
+    let mut unified = diff.unified_diff();
+    unified.context_radius(DIFF_CONTEXT);
+
+    let mut lines = vec![paint("--- expected", "-", color)];
+    lines.push(paint("+++ actual", "+", color));
+
+    for hunk in unified.iter_hunks() {
+        lines.push(paint(&hunk.header().to_string(), "@", color));
+
+        for change in hunk.iter_changes() {
+            let sign = match change.tag() {
+                ChangeTag::Delete => "-",
+                ChangeTag::Insert => "+",
+                ChangeTag::Equal => " ",
+            };
+            let line =
+                format!("{sign}{}", change.value().trim_end_matches('\n'));
+            lines.push(paint(&line, sign, color));
+        }
+    }
+
+    lines
+}
+
+fn paint(line: &str, sign: &str, color: bool) -> String {
+    match (color, sign) {
+        (false, _) => line.to_string(),
+        (true, "-") => line.red().to_string(),
+        (true, "+") => line.green().to_string(),
+        (true, "@") => line.cyan().to_string(),
+        (true, _) => line.dimmed().to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(expected: TestExpected, actual: &str) -> Option<OutputMismatch> {
+        output_check("stdout", &expected, actual, ".")
+    }
+
+    #[test]
+    fn ignores_trailing_newlines() {
+        let expected = TestExpected::Exact("hello\n\n".to_string());
+        assert!(check(expected.clone(), "hello").is_none());
+        assert!(check(expected, "hello\n").is_none());
+    }
+
+    #[test]
+    fn diffs_mismatched_text() {
+        let expected = TestExpected::Exact("a\nb\nc".to_string());
+        let mismatch = check(expected, "a\nx\nc\n").unwrap();
+
+        assert_eq!(mismatch.title(), "expected stdout");
+        assert_eq!(
+            mismatch.lines(false),
+            [
+                "--- expected",
+                "+++ actual",
+                "@@ -1,3 +1,3 @@",
+                " a",
+                "-b",
+                "+x",
+                " c"
+            ]
+        );
+    }
+
+    #[test]
+    fn matches_regex_on_each_line() {
+        let expected = TestExpected::Regex(r"^total: \d+$".to_string());
+        assert!(check(expected.clone(), "running\ntotal: 42\n").is_none());
+
+        let mismatch = check(expected, "total: none\n").unwrap();
+        assert!(matches!(mismatch, OutputMismatch::Regex { .. }));
+        assert_eq!(mismatch.lines(false), [r"/^total: \d+$/"]);
+
+        let invalid = check(TestExpected::Regex("(".to_string()), "");
+        assert!(matches!(invalid, Some(OutputMismatch::Invalid { .. })));
+    }
+
+    #[test]
+    fn reads_golden_files_from_the_workspace() {
+        let target = std::env::temp_dir()
+            .join(format!("dcs-expect-golden-{}", std::process::id()));
+        std::fs::create_dir_all(&target).unwrap();
+        std::fs::write(target.join("golden.txt"), "hello\n").unwrap();
+
+        let check = |file: &str, actual: &str| {
+            let expected = TestExpected::File(file.to_string());
+            output_check("stdout", &expected, actual, target.to_str().unwrap())
+        };
+
+        assert!(check("golden.txt", "hello").is_none());
+        assert!(matches!(
+            check("golden.txt", "bye"),
+            Some(OutputMismatch::Text { .. })
+        ));
+        assert!(matches!(
+            check("missing.txt", "hello"),
+            Some(OutputMismatch::Invalid { .. })
+        ));
+
+        std::fs::remove_dir_all(&target).unwrap();
+    }
+}
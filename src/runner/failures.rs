@@ -0,0 +1,289 @@
+//! Extraction of individual test failures from the output of test harnesses.
+//!
+//! Courses which declare their `language` can run their tests with a machine
+//! readable output, which is parsed to summarize failures above the raw output
+//! of a test:
+//!
+//! - Rust: `cargo test -- -Z unstable-options --format json`, the libtest
+//!   json format, which is only available on nightly or with
+//!   `RUSTC_BOOTSTRAP=1`.
+//! - Go: `go test -json`.
+//!
+//! Lines of `stdout` which are not valid json events are ignored, so any other
+//! output is left untouched.
+
+use indexmap::IndexMap;
+use serde::{Deserialize, Serialize};
+
+use crate::db::TestLanguage;
+
+/// Maximum number of lines of a failure message kept in the summary.
+const MESSAGE_LINES_MAX: usize = 5;
+
+/// A single failing test reported by a test harness.
+#[derive(Serialize, Debug, Clone)]
+pub struct TestFailure {
+    pub name: String,
+    pub message: String,
+    /// Location of the failure, as `file:line` or `file:line:column`.
+    pub location: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct LibtestEvent {
+    #[serde(rename = "type")]
+    kind: String,
+    event: String,
+    name: Option<String>,
+    stdout: Option<String>,
+    message: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct GoTestEvent {
+    action: String,
+    package: Option<String>,
+    test: Option<String>,
+    output: Option<String>,
+}
+
+/// Returns the failing tests reported in `stdout` by the test harness of
+/// `language`.
+pub fn failures_parse(
+    language: &TestLanguage,
+    stdout: &str,
+) -> Vec<TestFailure> {
+    match language {
+        TestLanguage::Rust => failures_parse_libtest(stdout),
+        TestLanguage::Go => failures_parse_go(stdout),
+    }
+}
+
+fn failures_parse_libtest(stdout: &str) -> Vec<TestFailure> {
+    stdout
+        .lines()
+        .filter_map(|line| serde_json::from_str::<LibtestEvent>(line).ok())
+        .filter(|event| event.kind == "test" && event.event == "failed")
+        .map(|event| {
+            let output = event.stdout.or(event.message).unwrap_or_default();
+            let (message, location) = panic_parse(&output);
+
+            TestFailure {
+                name: event.name.unwrap_or_default(),
+                message,
+                location,
+            }
+        })
+        .collect()
+}
+
+/// Extracts the message and location of a panic from the output of a test.
+///
+/// Recent versions of Rust format panics as:
+///
+/// ```text
+/// thread 'tests::it_works' panicked at src/lib.rs:10:5:
+/// assertion `left == right` failed
+/// ```
+///
+/// while older versions use:
+///
+/// ```text
+/// thread 'tests::it_works' panicked at 'explicit panic', src/lib.rs:10:5
+/// ```
+fn panic_parse(output: &str) -> (String, Option<String>) {
+    let mut lines =
+        output.lines().skip_while(|line| !line.contains("panicked at "));
+
+    let Some(header) = lines.next() else {
+        return (message_truncate(output.lines()), None);
+    };
+    let (_, panic) = header.split_once("panicked at ").unwrap_or_default();
+
+    match panic.strip_suffix(':') {
+        Some(location) => {
+            let message = message_truncate(lines.take_while(|line| {
+                !line.starts_with("note: ")
+                    && !line.starts_with("stack backtrace:")
+            }));
+            (message, Some(location.to_string()))
+        }
+        None => match panic.rsplit_once("', ") {
+            Some((message, location)) => (
+                message.trim_start_matches('\'').to_string(),
+                Some(location.to_string()),
+            ),
+            None => (panic.to_string(), None),
+        },
+    }
+}
+
+fn failures_parse_go(stdout: &str) -> Vec<TestFailure> {
+    let mut outputs = IndexMap::<(String, Option<String>), Vec<String>>::new();
+    let mut failures = vec![];
+
+    for event in stdout
+        .lines()
+        .filter_map(|line| serde_json::from_str::<GoTestEvent>(line).ok())
+    {
+        let key = (event.package.unwrap_or_default(), event.test);
+
+        match event.action.as_str() {
+            "output" => outputs
+                .entry(key)
+                .or_default()
+                .push(event.output.unwrap_or_default()),
+            "fail" => failures.push(key),
+            _ => {}
+        }
+    }
+
+    // Packages fail along with any of their tests, they are only reported if
+    // they failed on their own, for example because they did not compile
+    let tests_failed = failures.iter().any(|(_, test)| test.is_some());
+
+    failures
+        .into_iter()
+        .filter(|(_, test)| test.is_some() || !tests_failed)
+        .map(|key| {
+            let output = outputs.shift_remove(&key).unwrap_or_default();
+            let (package, test) = key;
+            let (message, location) = go_output_parse(&output);
+
+            TestFailure { name: test.unwrap_or(package), message, location }
+        })
+        .collect()
+}
+
+/// Extracts the message and location of a failure from the output of a go
+/// test, which reports errors as `    file_test.go:10: message`.
+fn go_output_parse(output: &[String]) -> (String, Option<String>) {
+    let lines = output.iter().map(|line| line.trim_end()).filter(|line| {
+        !line.is_empty()
+            && !line.starts_with("=== ")
+            && !line.starts_with("--- ")
+            && !line.starts_with("FAIL")
+    });
+
+    let located = lines.clone().find_map(|line| {
+        let (location, message) = line.trim_start().split_once(": ")?;
+        location
+            .contains(".go:")
+            .then(|| (message.to_string(), location.to_string()))
+    });
+
+    match located {
+        Some((message, location)) => (message, Some(location)),
+        None => (message_truncate(lines), None),
+    }
+}
+
+fn message_truncate<'a>(lines: impl Iterator<Item = &'a str>) -> String {
+    lines
+        .map(|line| line.trim_end())
+        .filter(|line| !line.is_empty())
+        .take(MESSAGE_LINES_MAX)
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIBTEST: &str = include_str!("fixtures/libtest.json");
+    const GO_TEST: &str = include_str!("fixtures/go_test.json");
+
+    fn summary(failures: &[TestFailure]) -> Vec<(&str, &str, Option<&str>)> {
+        failures
+            .iter()
+            .map(|failure| {
+                (
+                    failure.name.as_str(),
+                    failure.message.as_str(),
+                    failure.location.as_deref(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn libtest_failures() {
+        let failures = failures_parse(&TestLanguage::Rust, LIBTEST);
+
+        assert_eq!(
+            summary(&failures),
+            [
+                (
+                    "tests::it_adds",
+                    "assertion `left == right` failed\n  left: 4\n right: 5",
+                    Some("src/lib.rs:16:9")
+                ),
+                ("tests::it_panics", "explicit panic", Some("src/lib.rs:21:9")),
+            ]
+        );
+    }
+
+    #[test]
+    fn libtest_ignores_other_output() {
+        let stdout = format!("Hello from a test\n{LIBTEST}\nnot json {{");
+
+        assert_eq!(failures_parse(&TestLanguage::Rust, &stdout).len(), 2);
+        assert!(
+            failures_parse(&TestLanguage::Rust, "test result: ok").is_empty()
+        );
+    }
+
+    #[test]
+    fn libtest_panic_legacy() {
+        let output = "thread 'tests::it_panics' panicked at 'explicit panic', \
+                      src/lib.rs:21:9\nnote: run with `RUST_BACKTRACE=1`";
+
+        assert_eq!(
+            panic_parse(output),
+            ("explicit panic".to_string(), Some("src/lib.rs:21:9".to_string()))
+        );
+    }
+
+    #[test]
+    fn go_failures() {
+        let failures = failures_parse(&TestLanguage::Go, GO_TEST);
+
+        assert_eq!(
+            summary(&failures),
+            [
+                ("TestAdd", "Add(2, 2) = 5, want 4", Some("calc_test.go:10")),
+                (
+                    "TestDiv",
+                    "panic: runtime error: integer divide by zero \
+                     [recovered]\n\tpanic: runtime error: integer divide by \
+                     zero",
+                    None
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn go_package_failure() {
+        let stdout = [
+            r#"{"Action":"start","Package":"example.com/calc"}"#,
+            r#"{"Action":"output","Package":"example.com/calc","Output":"calc.go:3:1: syntax error: non-declaration statement outside function body\n"}"#,
+            r#"{"Action":"output","Package":"example.com/calc","Output":"FAIL\texample.com/calc [build failed]\n"}"#,
+            r#"{"Action":"fail","Package":"example.com/calc","Elapsed":0}"#,
+        ]
+        .join("\n");
+
+        let failures = failures_parse(&TestLanguage::Go, &stdout);
+
+        assert_eq!(
+            summary(&failures),
+            [(
+                "example.com/calc",
+                "syntax error: non-declaration statement outside function body",
+                Some("calc.go:3:1")
+            )]
+        );
+    }
+}
@@ -1,11 +1,20 @@
 use std::time::Duration;
 
+use colored::Colorize;
 use indicatif::{ProgressBar, ProgressStyle};
 
-use crate::monitor::StateMachine;
+use crate::{
+    db::TestState,
+    monitor::StateMachine,
+    parsing::{TestOutput, TestResult},
+};
 
 use self::v1::RunnerV1;
 
+pub mod expect;
+pub mod failures;
+pub mod process;
+pub mod sandbox;
 pub mod v1;
 
 pub enum RunnerVersion {
@@ -31,16 +40,93 @@ impl StateMachine for RunnerVersion {
 /// Format is as follows:
 ///
 /// ```bash
-/// ╭─[ output ]
+/// ╭─[ 1 failed test ]
+/// │ ✗ {name} ({location})
+/// │   {message}
+/// ├─[ output ]
 /// │ {output}
+/// ├─[ expected stdout ]
+/// │ {diff}
 /// ╰─[ {msg} ]
 /// ```
 ///
 /// * `output`: test output.
 /// * `msg`: custom message to display after the output.
-fn format_output(output: &str, msg: &str) -> String {
-    let output = output.replace("\n", "\n    │");
-    format!("    ╭─[ output ]{output}\n    ╰─[ {msg} ]")
+///
+/// Failures reported by the test harness and differences with the expected
+/// output are only displayed if there are any. If `failed` is set, everything
+/// but these differences is displayed in red.
+fn format_output(output: &TestOutput, msg: &str, failed: bool) -> String {
+    let paint = |text: String| match failed {
+        true => text.red().dimmed().to_string(),
+        false => text,
+    };
+
+    let mut formatted = String::new();
+
+    if !output.failures.is_empty() {
+        let count = output.failures.len();
+        let plural = if count == 1 { "" } else { "s" };
+        formatted.push_str(&format!("    ╭─[ {count} failed test{plural} ]"));
+
+        for failure in output.failures.iter() {
+            let location = match &failure.location {
+                Some(location) => format!(" ({location})"),
+                None => String::default(),
+            };
+            formatted.push_str(&format!(
+                "\n    │ {} {}{}",
+                "✗".red(),
+                failure.name.bold(),
+                location.dimmed()
+            ));
+            for line in failure.message.lines() {
+                formatted.push_str(&format!("\n    │   {line}"));
+            }
+        }
+
+        formatted.push_str("\n    ├─[ output ]");
+    } else {
+        formatted.push_str(&paint("    ╭─[ output ]".to_string()));
+    }
+
+    let text = output.to_string().replace("\n", "\n    │");
+    formatted.push_str(&paint(text));
+
+    for mismatch in output.mismatches.iter() {
+        formatted.push_str(&paint(format!("\n    ├─[ {} ]", mismatch.title())));
+        for line in mismatch.lines(true) {
+            formatted
+                .push_str(&format!("\n    {} {line}", paint("│".to_string())));
+        }
+    }
+
+    formatted.push_str(&paint(format!("\n    ╰─[ {msg} ]")));
+    formatted
+}
+
+/// Formats the output of a test along with its success or failure message, as
+/// displayed to the student.
+pub fn format_result(test: &TestState, result: &TestResult) -> String {
+    match result {
+        TestResult::Pass(output) => format_output(
+            output,
+            &format!("✅ {}", test.message_on_success),
+            false,
+        ),
+        TestResult::Fail(output) => {
+            format_output(output, &format!("❌ {}", test.message_on_fail), true)
+        }
+        TestResult::Timeout(output, timeout) => format_output(
+            output,
+            &format!(
+                "⏱ timed out after {}s: {}",
+                timeout.as_secs(),
+                test.message_on_fail
+            ),
+            true,
+        ),
+    }
 }
 
 #[allow(dead_code)]
@@ -52,7 +138,7 @@ fn format_spinner(progress: &ProgressBar) {
     progress.enable_steady_tick(Duration::from_millis(50));
 }
 
-fn format_bar(progress: &ProgressBar) {
+pub fn format_bar(progress: &ProgressBar) {
     progress.set_style(
         ProgressStyle::with_template("{wide_bar} {message} {elapsed_precise}")
             .unwrap(),
@@ -0,0 +1,245 @@
+//! Execution of test commands under resource limits.
+//!
+//! Test commands are provided by course authors and run against student code,
+//! so they cannot be trusted to terminate or to behave. Each command is spawned
+//! in its own process group so that it can be killed along with any child
+//! processes it might have created once a limit is exceeded.
+//!
+//! Since test processes do not belong to the foreground process group of the
+//! terminal, they do not receive `SIGINT` when the user hits Ctrl-C. Running
+//! process groups are tracked so that they can be killed by [`interrupt`].
+
+use std::{
+    collections::HashSet,
+    io::{Read, Write},
+    os::unix::process::CommandExt,
+    process::{Child, Command, ExitStatus, Stdio},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+use lazy_static::lazy_static;
+
+use crate::db::TestLimits;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+const BYTES_PER_MIB: u64 = 1024 * 1024;
+
+lazy_static! {
+    static ref PROCESS_GROUPS: Mutex<HashSet<u32>> = Mutex::new(HashSet::new());
+}
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Outcome of a process run under [`TestLimits`].
+pub enum ProcessOutcome {
+    /// The process exited on its own.
+    Exited(ExitStatus),
+    /// The process was killed after running for longer than its timeout.
+    TimedOut(Duration),
+    /// The process was killed after writing more than its output limit.
+    OutputExceeded(u64),
+}
+
+pub struct ProcessOutput {
+    pub outcome: ProcessOutcome,
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+    pub duration: Duration,
+}
+
+/// Spawns `command` and waits for it to complete, enforcing `limits`.
+///
+/// `stdout` and `stderr` are always captured. If `limits.max_output_bytes` is
+/// set, each stream is truncated to that many bytes. `stdin` is written to the
+/// process if provided, otherwise it reads from an empty input.
+pub fn run_limited(
+    mut command: Command,
+    limits: &TestLimits,
+    stdin: Option<Vec<u8>>,
+) -> Result<ProcessOutput, std::io::Error> {
+    command
+        .stdin(if stdin.is_some() { Stdio::piped() } else { Stdio::null() })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .process_group(0);
+
+    let rlimits = [
+        (
+            libc::RLIMIT_AS,
+            limits.max_memory.map(|mib| mib.saturating_mul(BYTES_PER_MIB)),
+        ),
+        (libc::RLIMIT_NPROC, limits.max_processes),
+    ];
+
+    for (resource, value) in rlimits {
+        let Some(value) = value else {
+            continue;
+        };
+
+        // SAFETY: `setrlimit` is async-signal-safe and only affects the
+        // forked child, before `exec` is called.
+        unsafe {
+            command.pre_exec(move || {
+                let limit = libc::rlimit {
+                    rlim_cur: value as libc::rlim_t,
+                    rlim_max: value as libc::rlim_t,
+                };
+                if libc::setrlimit(resource, &limit) != 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+    }
+
+    // The process is spawned while holding the lock taken by `interrupt`, so
+    // that it is either never spawned or always killed on Ctrl-C
+    let start = Instant::now();
+    let mut child = {
+        let mut process_groups = PROCESS_GROUPS.lock().unwrap();
+        if INTERRUPTED.load(Ordering::Relaxed) {
+            return Err(std::io::ErrorKind::Interrupted.into());
+        }
+
+        let child = command.spawn()?;
+        process_groups.insert(child.id());
+        child
+    };
+
+    if let (Some(mut pipe), Some(input)) = (child.stdin.take(), stdin) {
+        // Written from a separate thread as the process might not read its
+        // whole input, or only after writing its own output
+        std::thread::spawn(move || {
+            let _ = pipe.write_all(&input);
+        });
+    }
+
+    let exceeded = Arc::new(AtomicBool::new(false));
+    let stdout = output_capture(
+        child.stdout.take(),
+        limits.max_output_bytes,
+        exceeded.clone(),
+    );
+    let stderr = output_capture(
+        child.stderr.take(),
+        limits.max_output_bytes,
+        exceeded.clone(),
+    );
+
+    let timeout = limits.timeout.map(Duration::from_secs);
+
+    let outcome = loop {
+        if let Some(status) = child.try_wait()? {
+            // Background processes left behind by the test would otherwise
+            // keep its output streams open
+            process_group_kill(&child);
+            break ProcessOutcome::Exited(status);
+        }
+
+        if let Some(timeout) = timeout {
+            if start.elapsed() >= timeout {
+                log::debug!("test timed out after {}s", timeout.as_secs());
+                process_kill(&mut child);
+                break ProcessOutcome::TimedOut(timeout);
+            }
+        }
+
+        if exceeded.load(Ordering::Relaxed) {
+            let max_output_bytes = limits.max_output_bytes.unwrap_or_default();
+            log::debug!("test exceeded output limit of {max_output_bytes}B");
+            process_kill(&mut child);
+            break ProcessOutcome::OutputExceeded(max_output_bytes);
+        }
+
+        std::thread::sleep(POLL_INTERVAL);
+    };
+
+    PROCESS_GROUPS.lock().unwrap().remove(&child.id());
+    let duration = start.elapsed();
+
+    let stdout = stdout.join().unwrap_or_default();
+    let stderr = stderr.join().unwrap_or_default();
+
+    Ok(ProcessOutput { outcome, stdout, stderr, duration })
+}
+
+/// Kills every running test process group. Once called, no new test process
+/// will be spawned.
+pub fn interrupt() {
+    INTERRUPTED.store(true, Ordering::Relaxed);
+
+    for pgid in PROCESS_GROUPS.lock().unwrap().drain() {
+        // SAFETY: `kill` has no memory safety requirements.
+        unsafe {
+            libc::kill(-(pgid as libc::pid_t), libc::SIGKILL);
+        }
+    }
+}
+
+/// Whether the user has interrupted the CLI with Ctrl-C.
+pub fn is_interrupted() -> bool {
+    INTERRUPTED.load(Ordering::Relaxed)
+}
+
+/// Reads `stream` to completion on a separate thread, keeping at most
+/// `max_bytes` bytes. `exceeded` is raised once more than `max_bytes` bytes
+/// have been written to the stream.
+fn output_capture<R>(
+    stream: Option<R>,
+    max_bytes: Option<u64>,
+    exceeded: Arc<AtomicBool>,
+) -> JoinHandle<Vec<u8>>
+where
+    R: Read + Send + 'static,
+{
+    std::thread::spawn(move || {
+        let mut output = vec![];
+        let Some(mut stream) = stream else {
+            return output;
+        };
+
+        let mut buffer = [0u8; 8192];
+        loop {
+            let n = match stream.read(&mut buffer) {
+                Ok(0) | Err(_) => break,
+                Ok(n) => n,
+            };
+
+            match max_bytes {
+                Some(max_bytes) => {
+                    let left =
+                        (max_bytes as usize).saturating_sub(output.len());
+                    output.extend_from_slice(&buffer[..n.min(left)]);
+
+                    if n > left {
+                        exceeded.store(true, Ordering::Relaxed);
+                    }
+                }
+                None => output.extend_from_slice(&buffer[..n]),
+            }
+        }
+
+        output
+    })
+}
+
+/// Kills `child` and waits for it to exit.
+fn process_kill(child: &mut Child) {
+    process_group_kill(child);
+    let _ = child.wait();
+}
+
+/// Kills the process group led by `child`, so that processes spawned by the
+/// test command do not outlive it.
+fn process_group_kill(child: &Child) {
+    // SAFETY: `kill` has no memory safety requirements. The child was spawned
+    // as the leader of its own process group, so its pid is also the group id.
+    unsafe {
+        libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL);
+    }
+}
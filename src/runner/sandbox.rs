@@ -0,0 +1,343 @@
+//! Isolation of test commands on Linux.
+//!
+//! Test commands come from tester definitions which are downloaded from a
+//! remote URL. When sandboxing is enabled, each command runs in its own user,
+//! mount and network namespaces, where:
+//!
+//! - there is no network access, not even to the loopback interface,
+//! - the whole filesystem is read-only, except for the tester workspace,
+//! - `/tmp` is a private, empty tmpfs,
+//! - the cargo home directory is writable, but changes are made to a copy of
+//!   it kept in `/tmp`, so that cargo can lock it and unpack cached crates
+//!   without tests being able to tamper with it.
+//!
+//! This relies on unprivileged user namespaces and `mount_setattr`, available
+//! since Linux 5.12. Support is checked once when a [`Sandbox`] is created so
+//! that students get a clear error up front rather than failing tests.
+
+use std::{
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum SandboxError {
+    #[error(
+        "sandboxing is not supported on this system: {0}. Sandboxing requires \
+         Linux 5.12 or later with unprivileged user namespaces enabled"
+    )]
+    Unsupported(String),
+    #[error("failed to resolve tester workspace '{0}': {1}")]
+    Workspace(String, String),
+}
+
+/// Directory in the sandbox's `/tmp` holding changes made to the cargo home.
+#[cfg(target_os = "linux")]
+const DIR_CARGO_OVERLAY: &str = "/tmp/.dcs-cargo";
+
+/// Runs test commands in isolation from the rest of the system.
+#[derive(Debug, Clone)]
+pub struct Sandbox {
+    workspace: PathBuf,
+    /// Cargo home directory, if it exists.
+    cargo_home: Option<PathBuf>,
+}
+
+impl Sandbox {
+    /// Creates a sandbox in which only `workspace` is writable, checking that
+    /// the host supports it.
+    pub fn new(workspace: &Path) -> Result<Self, SandboxError> {
+        let workspace = workspace.canonicalize().map_err(|err| {
+            SandboxError::Workspace(
+                workspace.display().to_string(),
+                err.to_string(),
+            )
+        })?;
+
+        let cargo_home = std::env::var_os("CARGO_HOME")
+            .map(PathBuf::from)
+            .or_else(|| dirs::home_dir().map(|home| home.join(".cargo")))
+            .and_then(|dir| dir.canonicalize().ok())
+            .filter(|dir| dir.is_dir());
+
+        let sandbox = Self { workspace, cargo_home };
+
+        log::debug!("checking sandbox support");
+
+        let mut command = Command::new("true");
+        command
+            .current_dir(&sandbox.workspace)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null());
+        sandbox.apply(&mut command)?;
+
+        match command.status() {
+            Ok(status) if status.success() => Ok(sandbox),
+            Ok(status) => Err(SandboxError::Unsupported(format!(
+                "test process exited with {status}"
+            ))),
+            Err(err) => Err(SandboxError::Unsupported(err.to_string())),
+        }
+    }
+
+    /// Configures `command` to run inside the sandbox.
+    #[cfg(target_os = "linux")]
+    pub fn apply(&self, command: &mut Command) -> Result<(), SandboxError> {
+        use std::os::unix::process::CommandExt;
+
+        let cwd = match command.get_current_dir() {
+            Some(cwd) => cwd.canonicalize().map_err(|err| {
+                SandboxError::Workspace(
+                    cwd.display().to_string(),
+                    err.to_string(),
+                )
+            })?,
+            None => self.workspace.clone(),
+        };
+
+        let setup = linux::SandboxSetup::new(
+            &self.workspace,
+            &cwd,
+            self.cargo_home.as_deref(),
+        )
+        .map_err(|err| SandboxError::Unsupported(err.to_string()))?;
+
+        // SAFETY: `SandboxSetup::enter` only performs system calls on data
+        // which was allocated before forking.
+        unsafe {
+            command.pre_exec(move || setup.enter());
+        }
+
+        Ok(())
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn apply(&self, _command: &mut Command) -> Result<(), SandboxError> {
+        Err(SandboxError::Unsupported("only Linux is supported".to_string()))
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use std::{ffi::CString, io::Error, os::unix::ffi::OsStrExt, path::Path};
+
+    use super::DIR_CARGO_OVERLAY;
+
+    const OPEN_TREE_CLONE: libc::c_uint = 0x1;
+    const MOVE_MOUNT_F_EMPTY_PATH: libc::c_uint = 0x4;
+    const MOUNT_ATTR_RDONLY: u64 = 0x1;
+
+    #[repr(C)]
+    struct MountAttr {
+        attr_set: u64,
+        attr_clr: u64,
+        propagation: u64,
+        userns_fd: u64,
+    }
+
+    /// Everything needed to enter the sandbox from a forked child process.
+    /// No allocation may happen after forking, so all paths and file contents
+    /// are prepared ahead of time.
+    pub struct SandboxSetup {
+        workspace: CString,
+        /// Parent directories of the workspace, which need to be re-created
+        /// if the workspace is located under `/tmp`.
+        workspace_parents: Vec<CString>,
+        /// Working directory of the test process, entered again once the
+        /// workspace has been mounted back.
+        cwd: CString,
+        /// Cargo home directory and the options of the overlay mounted on
+        /// top of it.
+        cargo_overlay: Option<(CString, CString)>,
+        /// Directories of the overlay, created once `/tmp` is mounted.
+        cargo_overlay_dirs: [CString; 3],
+        uid_map: Vec<u8>,
+        gid_map: Vec<u8>,
+    }
+
+    impl SandboxSetup {
+        pub fn new(
+            workspace: &Path,
+            cwd: &Path,
+            cargo_home: Option<&Path>,
+        ) -> Result<Self, Error> {
+            let cstring = |path: &Path| {
+                CString::new(path.as_os_str().as_bytes()).map_err(Error::other)
+            };
+
+            let mut workspace_parents = workspace
+                .ancestors()
+                .skip(1)
+                .filter(|path| path.starts_with("/tmp") && *path != "/tmp")
+                .map(cstring)
+                .collect::<Result<Vec<_>, _>>()?;
+            workspace_parents.reverse();
+
+            let dir_upper = format!("{DIR_CARGO_OVERLAY}/upper");
+            let dir_work = format!("{DIR_CARGO_OVERLAY}/work");
+
+            // Overlay options are separated by commas and colons, and a cargo
+            // home under `/tmp` is hidden by the tmpfs anyway
+            let cargo_overlay = cargo_home
+                .filter(|dir| {
+                    let dir = dir.to_string_lossy();
+                    !dir.contains([',', ':']) && !dir.starts_with("/tmp")
+                })
+                .map(|dir| {
+                    let options = format!(
+                        "lowerdir={},upperdir={dir_upper},workdir={dir_work}",
+                        dir.display()
+                    );
+                    Ok::<_, Error>((
+                        cstring(dir)?,
+                        CString::new(options).map_err(Error::other)?,
+                    ))
+                })
+                .transpose()?;
+            let cargo_overlay_dirs = [
+                CString::new(DIR_CARGO_OVERLAY).map_err(Error::other)?,
+                CString::new(dir_upper).map_err(Error::other)?,
+                CString::new(dir_work).map_err(Error::other)?,
+            ];
+
+            // SAFETY: `getuid` and `getgid` always succeed.
+            let (uid, gid) = unsafe { (libc::getuid(), libc::getgid()) };
+
+            Ok(Self {
+                workspace: cstring(workspace)?,
+                workspace_parents,
+                cwd: cstring(cwd)?,
+                cargo_overlay,
+                cargo_overlay_dirs,
+                uid_map: format!("{uid} {uid} 1\n").into_bytes(),
+                gid_map: format!("{gid} {gid} 1\n").into_bytes(),
+            })
+        }
+
+        /// Moves the current process into the sandbox. Only called between
+        /// `fork` and `exec`.
+        pub fn enter(&self) -> Result<(), Error> {
+            // SAFETY: all pointers passed to system calls below point to
+            // null-terminated strings or structs which outlive the calls.
+            unsafe {
+                check(libc::unshare(
+                    libc::CLONE_NEWUSER
+                        | libc::CLONE_NEWNS
+                        | libc::CLONE_NEWNET
+                        | libc::CLONE_NEWIPC,
+                ))?;
+
+                // Keeps the same user and group inside the sandbox
+                file_write(c"/proc/self/setgroups", b"deny")?;
+                file_write(c"/proc/self/uid_map", &self.uid_map)?;
+                file_write(c"/proc/self/gid_map", &self.gid_map)?;
+
+                // Changes to mounts must not propagate back to the host
+                check(libc::mount(
+                    std::ptr::null(),
+                    c"/".as_ptr(),
+                    std::ptr::null(),
+                    libc::MS_REC | libc::MS_PRIVATE,
+                    std::ptr::null(),
+                ))?;
+
+                // Detached copy of the workspace, which stays writable
+                let workspace = check(libc::syscall(
+                    libc::SYS_open_tree,
+                    libc::AT_FDCWD,
+                    self.workspace.as_ptr(),
+                    OPEN_TREE_CLONE
+                        | libc::O_CLOEXEC as libc::c_uint
+                        | libc::AT_RECURSIVE as libc::c_uint,
+                ) as libc::c_int)?;
+
+                let attr = MountAttr {
+                    attr_set: MOUNT_ATTR_RDONLY,
+                    attr_clr: 0,
+                    propagation: 0,
+                    userns_fd: 0,
+                };
+                check(libc::syscall(
+                    libc::SYS_mount_setattr,
+                    libc::AT_FDCWD,
+                    c"/".as_ptr(),
+                    libc::AT_RECURSIVE as libc::c_uint,
+                    &attr as *const MountAttr,
+                    std::mem::size_of::<MountAttr>(),
+                ) as libc::c_int)?;
+
+                check(libc::mount(
+                    c"tmpfs".as_ptr(),
+                    c"/tmp".as_ptr(),
+                    c"tmpfs".as_ptr(),
+                    libc::MS_NOSUID | libc::MS_NODEV,
+                    std::ptr::null(),
+                ))?;
+
+                for parent in self.workspace_parents.iter() {
+                    libc::mkdir(parent.as_ptr(), 0o755);
+                }
+                libc::mkdir(self.workspace.as_ptr(), 0o755);
+
+                // Unprivileged overlays might be disabled, in which case the
+                // cargo home is left read-only
+                if let Some((cargo_home, options)) = &self.cargo_overlay {
+                    for dir in self.cargo_overlay_dirs.iter() {
+                        libc::mkdir(dir.as_ptr(), 0o755);
+                    }
+
+                    libc::mount(
+                        c"overlay".as_ptr(),
+                        cargo_home.as_ptr(),
+                        c"overlay".as_ptr(),
+                        libc::MS_NOSUID | libc::MS_NODEV,
+                        options.as_ptr().cast(),
+                    );
+                }
+
+                check(libc::syscall(
+                    libc::SYS_move_mount,
+                    workspace,
+                    c"".as_ptr(),
+                    libc::AT_FDCWD,
+                    self.workspace.as_ptr(),
+                    MOVE_MOUNT_F_EMPTY_PATH,
+                ) as libc::c_int)?;
+                libc::close(workspace);
+
+                // The working directory still points to the read-only mount
+                check(libc::chdir(self.cwd.as_ptr()))?;
+            }
+
+            Ok(())
+        }
+    }
+
+    fn check(ret: libc::c_int) -> Result<libc::c_int, Error> {
+        if ret < 0 {
+            Err(Error::last_os_error())
+        } else {
+            Ok(ret)
+        }
+    }
+
+    /// Writes `content` to the file at `path` using raw system calls.
+    unsafe fn file_write(
+        path: &std::ffi::CStr,
+        content: &[u8],
+    ) -> Result<(), Error> {
+        let fd =
+            check(libc::open(path.as_ptr(), libc::O_WRONLY | libc::O_CLOEXEC))?;
+        let written = libc::write(fd, content.as_ptr().cast(), content.len());
+        libc::close(fd);
+
+        if written < 0 {
+            return Err(Error::last_os_error());
+        }
+
+        Ok(())
+    }
+}
@@ -1,34 +1,36 @@
-use std::net::TcpStream;
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Mutex,
+    },
+};
 
 use indicatif::ProgressBar;
 use parity_scale_codec::{Decode, Encode};
-use reqwest::{blocking::Client, StatusCode};
-use thiserror::Error;
-use tungstenite::{stream::MaybeTlsStream, Message, WebSocket};
 
 use crate::{
-    db::{PathLink, TestState, ValidationState},
-    models::TestLogEntry,
+    db::{TestState, ValidationState},
     monitor::StateMachine,
-    parsing::{
-        v1::redis::{RedisTestResultV1, RedisTestState},
-        TestResult,
-    },
+    parsing::{TestOutput, TestResult},
+    reporter::{Reporter, ReporterError, Reporters, RunStatus},
+    runner::{process::is_interrupted, sandbox::Sandbox},
 };
 
-use super::{format_bar, format_output};
+use super::format_bar;
 
 use colored::Colorize;
 
 /// Runs all the tests specified in a `tests.json` file.
 ///
-/// Tests are run sequentially in their order of definition. Running tests
-/// occurs in 3 steps:
+/// Tests are run sequentially in their order of definition, after the tests
+/// they depend on. Running tests occurs in 3 steps:
 ///
 /// 1. Loading the `tests.json` file.
 /// 2. Executing tests one by one, displaying `stderr` and `stdout` as
 ///    appropriate.
-/// 3. Test stop running once all test have been run or a mandatory test fails.
+/// 3. Test stop running once all test have been run. Tests which depend on a
+///    failed mandatory test are not run and are reported as blocked.
 /// 4. A summary of the run is displayed at the end of the process.
 ///
 /// # `tests.json` file format
@@ -69,7 +71,7 @@ use colored::Colorize;
 ///     ]
 /// }
 /// ```
-/// 
+///
 /// Test suites marked as optional do not need to be passed for the course to be
 /// validated. They will however still count towards the overall success of the
 /// course, so if a student passes 9 mandatory test suites but fails 1 optional
@@ -85,18 +87,68 @@ use colored::Colorize;
 ///     "message_on_success": "This test passed, congrats!"
 /// }
 /// ```
-/// 
-/// `cmd` defines which command to run for the test to execute. Like test
-/// suites, tests can be marked as `optional`. `optional` tests will still count
-/// towards the overall success of the course but do not need to be validated as
-// part of a test suite.
+///
+/// `cmd` defines which command to run for the test to execute. It can be a
+/// list of arguments or a string, which is split following shell quoting rules.
+/// Strings are only run through `sh -c` if the test sets `"shell": true`.
+/// Tests can also set `env` variables, a `cwd` relative to the tester
+/// workspace and a `stdin`, either as a string or as `{ "file": "<path>" }`.
+/// By default, tests pass if `cmd` exits successfully. They can also set an
+/// `expected_stdout` or `expected_stderr`, either as a string, as
+/// `{ "regex": "<pattern>" }` or as a golden `{ "file": "<path>" }`.
+///
+/// Like test suites, tests can be marked as `optional`. `optional` tests will
+/// still count towards the overall success of the course but do not need to be
+/// validated as part of a test suite.
+///
+/// Tests can optionally set a `timeout` (in seconds), `max_memory` (in MiB),
+/// `max_output_bytes` and `max_processes`. Tests exceeding their timeout are
+/// stopped and reported as failed. `max_processes` guards against fork bombs
+/// but counts every process and thread of the student, not only those of the
+/// test, so it should be set well above what a test needs. Course-wide
+/// defaults can be set under `limits` at the root of the tester definition.
+///
+/// Courses which set their `language` to `rust` or `go` can run tests with
+/// `cargo test -- -Z unstable-options --format json` or `go test -json`, in
+/// which case failing tests are summarized above the output of the test. The
+/// libtest json format is unstable: it requires a nightly toolchain, or
+/// setting `RUSTC_BOOTSTRAP=1` in the `env` of the test on stable.
+///
+/// By default, each test depends on the test defined before it. Tests and
+/// lessons can instead list the slugs of the tests or lessons they depend on
+/// under `depends_on`, in which case the first test of a lesson inherits the
+/// dependencies of its lesson. Slugs are only unique within a lesson: tests of
+/// other lessons can be written as 'lesson/test', and must be if their slug is
+/// used in several lessons. Tests without dependencies form the first stage
+/// of the course and each following stage depends on the ones before it:
+/// staggered mode unlocks the course one stage at a time.
+///
+/// Tests marked as `independent` can be run concurrently with neighbouring
+/// independent tests when more than one job is requested. Their results are
+/// still reported in order of definition. By default, they do not depend on
+/// each other but on the last test before them which is not independent, and
+/// the test after them depends on all of them.
+///
 /// * `progress`: number of tests left to run.
 /// * `course`: deserialized course information.
 pub struct RunnerV1 {
     progress: ProgressBar,
     target: String,
     tree: sled::Tree,
-    client: WebSocket<MaybeTlsStream<TcpStream>>,
+    /// Destinations test results are reported to.
+    reporter: Reporters,
+    /// Maximum number of independent tests to run concurrently.
+    jobs: usize,
+    /// Isolates tests from the rest of the system, if enabled.
+    sandbox: Option<Sandbox>,
+    /// Results of independent tests which have already been run in parallel
+    /// but not yet reported, by test index.
+    prefetched: HashMap<usize, TestResult>,
+    /// Tests which block the tests depending on them, by id, along with the
+    /// name of the failing test at the origin of the block.
+    blocking: HashMap<String, String>,
+    /// First mandatory test which failed, and why.
+    failure: Option<(usize, String)>,
     tests: Vec<(sled::IVec, TestState)>,
     success: u32,
     state: RunnerStateV1,
@@ -108,9 +160,20 @@ pub struct RunnerV1 {
 #[derive(Eq, PartialEq, Clone)]
 pub enum RunnerStateV1 {
     Loaded,
-    NewTest { index_test: usize },
-    Fail { index_test: usize, err: String },
+    NewTest {
+        index_test: usize,
+    },
+    Fail {
+        index_test: usize,
+        err: String,
+    },
     Pass,
+    /// No test was selected. Nothing is recorded and staggered progress is
+    /// left unchanged.
+    Empty,
+    /// The user hit Ctrl-C. The test which was running when interrupted did
+    /// not fail and is neither recorded nor reported.
+    Interrupted,
     Finish,
 }
 
@@ -120,9 +183,14 @@ impl StateMachine for RunnerV1 {
             progress,
             tree,
             ref target,
-            mut client,
+            mut reporter,
+            jobs,
+            sandbox,
+            mut prefetched,
+            mut blocking,
+            mut failure,
             tests,
-            success,
+            mut success,
             state,
             on_pass,
             on_fail,
@@ -133,246 +201,253 @@ impl StateMachine for RunnerV1 {
             // Genesis state, displays information about the course and the
             // number of exercises left.
             RunnerStateV1::Loaded => {
-                progress.println(format!(
-                    "\n📒 You have {} exercises left",
-                    tests.len().to_string().bold()
-                ));
+                reporter_warn(reporter.run_start(&tests), &progress);
+
+                let state = if tests.is_empty() {
+                    RunnerStateV1::Empty
+                } else {
+                    RunnerStateV1::NewTest { index_test: 0 }
+                };
 
                 format_bar(&progress);
 
-                if tests.is_empty() {
-                    Self {
-                        progress,
-                        tree,
-                        target: target.to_string(),
-                        client,
-                        tests,
-                        success,
-                        state: RunnerStateV1::Fail {
-                            index_test: 0,
-                            err: "🚫 no tests found".to_string(),
-                        },
-                        on_pass,
-                        on_fail,
-                        on_finish,
-                    }
-                } else {
-                    Self {
-                        progress,
-                        tree,
-                        target: target.to_string(),
-                        client,
-                        tests,
-                        success,
-                        state: RunnerStateV1::NewTest { index_test: 0 },
-                        on_pass,
-                        on_fail,
-                        on_finish,
-                    }
+                Self {
+                    progress,
+                    tree,
+                    target: target.to_string(),
+                    reporter,
+                    jobs,
+                    sandbox,
+                    prefetched,
+                    blocking,
+                    failure,
+                    tests,
+                    success,
+                    state,
+                    on_pass,
+                    on_fail,
+                    on_finish,
                 }
             }
             // Runs the current test. This state is responsible for exiting
             // into a Failed state in case a mandatory test
             // does not pass.
+            RunnerStateV1::NewTest { index_test } if is_interrupted() => {
+                log::debug!("interrupted before test {index_test}");
+
+                Self {
+                    progress,
+                    tree,
+                    target: target.to_string(),
+                    reporter,
+                    jobs,
+                    sandbox,
+                    prefetched,
+                    blocking,
+                    failure,
+                    tests,
+                    success,
+                    state: RunnerStateV1::Interrupted,
+                    on_pass,
+                    on_fail,
+                    on_finish,
+                }
+            }
             RunnerStateV1::NewTest { index_test } => {
-                progress.println(format!("{}", &tests[index_test].1));
+                let (key, test) = &tests[index_test];
 
                 progress.inc(1);
 
-                // Testing happens HERE
-                let success_inc = match &tests[index_test].1.run(&target) {
-                    TestResult::Pass(stdout) => {
-                        let query = tree
-                            .update_and_fetch(&tests[index_test].0, test_pass);
-
-                        if query.is_err() || matches!(query, Ok(None)) {
-                            let state = RunnerStateV1::Fail {
-                                index_test,
-                                err: format!(
-                                    "failed to update test {}",
-                                    tests[index_test].1.name
-                                ),
-                            };
+                // Tests are not run if one of their dependencies failed, and in
+                // turn block any test which depends on them
+                let blocker = test
+                    .depends_on
+                    .iter()
+                    .find_map(|id| blocking.get(id))
+                    .cloned();
 
-                            return Self {
-                                progress,
-                                tree,
-                                target: target.to_string(),
-                                client,
-                                tests,
-                                success,
-                                state,
-                                on_pass,
-                                on_fail,
-                                on_finish,
-                            };
-                        }
+                if let Some(blocker) = blocker {
+                    log::debug!("test {} blocked by {blocker}", test.name);
 
-                        let output = format_output(
-                            stdout,
-                            &format!(
-                                "✅ {}",
-                                tests[index_test].1.message_on_success
-                            ),
-                        );
-
-                        let test_result = RedisTestResultV1::pass(
-                            &tests[index_test].1.slug,
-                            &output,
-                        );
-
-                        if let Err(e) = json_report_test(
-                            test_result,
-                            &mut client,
-                            &tests[index_test].1,
-                            &self.target,
-                        ) {
-                            return Self {
-                                progress,
-                                tree,
-                                target: target.to_string(),
-                                client,
-                                tests,
-                                success,
-                                state: RunnerStateV1::Fail {
-                                    index_test,
-                                    err: e.to_string(),
-                                },
-                                on_pass,
-                                on_fail,
-                                on_finish,
-                            };
-                        }
+                    prefetched.remove(&index_test);
+                    let _ = tree.update_and_fetch(key, test_blocked);
 
-                        progress.println(output);
+                    reporter_warn(
+                        reporter.test_blocked(index_test, test, &blocker),
+                        &progress,
+                    );
 
-                        1
-                    }
-                    TestResult::Fail(stderr) => {
-                        let query = tree
-                            .update_and_fetch(&tests[index_test].0, test_fail);
-
-                        if query.is_err() || matches!(query, Ok(None)) {
-                            let state = RunnerStateV1::Fail {
-                                index_test,
-                                err: format!(
-                                    "failed to update test {}",
-                                    tests[index_test].1.name
-                                ),
-                            };
+                    blocking.insert(test.id(), blocker);
+                } else {
+                    reporter_warn(
+                        reporter.test_start(index_test, test),
+                        &progress,
+                    );
 
-                            return Self {
-                                progress,
-                                tree,
-                                target: target.to_string(),
-                                client,
-                                tests,
-                                success,
-                                state,
-                                on_pass,
-                                on_fail,
-                                on_finish,
-                            };
+                    // Testing happens HERE
+                    let result = match prefetched.remove(&index_test) {
+                        Some(result) => result,
+                        None if jobs > 1 && test.independent => {
+                            // Runs the following batch of independent tests
+                            // concurrently. Results are still reported one by
+                            // one in their order of definition.
+                            let index_end = tests[index_test..]
+                                .iter()
+                                .position(|(_, test)| !test.independent)
+                                .map_or(tests.len(), |n| index_test + n);
+
+                            log::debug!(
+                                "running tests {index_test}..{index_end} with {jobs} jobs"
+                            );
+
+                            let mut results = tests_run_parallel(
+                                &tests[index_test..index_end],
+                                target,
+                                sandbox.as_ref(),
+                                jobs,
+                                &blocking,
+                            )
+                            .into_iter();
+
+                            // Tests left out of the batch are either blocked
+                            // or run in a later batch
+                            let result =
+                                results.next().flatten().unwrap_or_else(|| {
+                                    TestResult::Fail(TestOutput::error(
+                                        "could not execute test",
+                                    ))
+                                });
+                            prefetched.extend(results.enumerate().filter_map(
+                                |(n, result)| {
+                                    Some((index_test + 1 + n, result?))
+                                },
+                            ));
+
+                            result
                         }
+                        None => test.run(target, sandbox.as_ref()),
+                    };
+
+                    // The test was killed on Ctrl-C
+                    if is_interrupted() {
+                        log::debug!("interrupted during test {index_test}");
+
+                        return Self {
+                            progress,
+                            tree,
+                            target: target.to_string(),
+                            reporter,
+                            jobs,
+                            sandbox,
+                            prefetched,
+                            blocking,
+                            failure,
+                            tests,
+                            success,
+                            state: RunnerStateV1::Interrupted,
+                            on_pass,
+                            on_fail,
+                            on_finish,
+                        };
+                    }
 
-                        let output = format_output(
-                            stderr,
-                            &format!(
-                                "❌ {}",
-                                tests[index_test].1.message_on_fail
-                            ),
-                        )
-                        .red()
-                        .dimmed()
-                        .to_string();
-
-                        let test_result = RedisTestResultV1::fail(
-                            &tests[index_test].1.slug,
-                            &output,
-                            tests[index_test].1.optional,
-                        );
-
-                        if let Err(e) = json_report_test(
-                            test_result,
-                            &mut client,
-                            &tests[index_test].1,
-                            &self.target,
-                        ) {
-                            return Self {
-                                progress,
-                                tree,
-                                target: target.to_string(),
-                                client,
-                                tests,
-                                success,
-                                state: RunnerStateV1::Fail {
-                                    index_test,
-                                    err: e.to_string(),
-                                },
-                                on_pass,
-                                on_fail,
-                                on_finish,
-                            };
+                    let query = match result {
+                        TestResult::Pass(_) => {
+                            tree.update_and_fetch(key, test_pass)
+                        }
+                        TestResult::Fail(_) => {
+                            tree.update_and_fetch(key, test_fail)
                         }
+                        TestResult::Timeout(..) => {
+                            tree.update_and_fetch(key, test_timeout)
+                        }
+                    };
+
+                    if query.is_err() || matches!(query, Ok(None)) {
+                        let state = RunnerStateV1::Fail {
+                            index_test,
+                            err: format!("failed to update test {}", test.name),
+                        };
+
+                        return Self {
+                            progress,
+                            tree,
+                            target: target.to_string(),
+                            reporter,
+                            jobs,
+                            sandbox,
+                            prefetched,
+                            blocking,
+                            failure,
+                            tests,
+                            success,
+                            state,
+                            on_pass,
+                            on_fail,
+                            on_finish,
+                        };
+                    }
 
-                        progress.println(output);
+                    reporter_warn(
+                        reporter.test_result(index_test, test, &result),
+                        &progress,
+                    );
 
-                        if !tests[index_test].1.optional {
-                            let state = RunnerStateV1::Fail {
-                                index_test,
-                                err: format!(
+                    // Failing mandatory tests fail the run, but tests which do
+                    // not depend on them are still run
+                    match result {
+                        TestResult::Pass(_) => success += 1,
+                        TestResult::Fail(_) | TestResult::Timeout(..)
+                            if test.optional => {}
+                        TestResult::Fail(_) | TestResult::Timeout(..) => {
+                            let err = match result {
+                                TestResult::Timeout(_, timeout) => format!(
+                                    "Test {}:{} timed out after {}s",
+                                    index_test,
+                                    &test.name,
+                                    timeout.as_secs()
+                                ),
+                                _ => format!(
                                     "Test {}:{} failed",
-                                    index_test, &tests[index_test].1.name
+                                    index_test, &test.name
                                 ),
                             };
 
-                            return Self {
-                                progress,
-                                tree,
-                                target: target.to_string(),
-                                client,
-                                tests,
-                                success,
-                                state,
-                                on_pass,
-                                on_fail,
-                                on_finish,
-                            };
+                            blocking.insert(test.id(), test.name.clone());
+                            failure.get_or_insert((index_test, err));
                         }
+                    };
+                }
 
-                        0
+                // Moves on to the next test or marks the tests as Passed or
+                // Failed
+                let state = if index_test + 1 < tests.len() {
+                    RunnerStateV1::NewTest { index_test: index_test + 1 }
+                } else {
+                    match failure.take() {
+                        Some((index_test, err)) => {
+                            RunnerStateV1::Fail { index_test, err }
+                        }
+                        None => RunnerStateV1::Pass,
                     }
                 };
 
-                // Moves on to the next test or marks the tests as Passed
-                if index_test + 1 < tests.len() {
-                    Self {
-                        progress,
-                        tree,
-                        target: target.to_string(),
-                        client,
-                        tests,
-                        success: success + success_inc,
-                        state: RunnerStateV1::NewTest {
-                            index_test: index_test + 1,
-                        },
-                        on_pass,
-                        on_fail,
-                        on_finish,
-                    }
-                } else {
-                    Self {
-                        progress,
-                        tree,
-                        target: target.to_string(),
-                        client,
-                        tests,
-                        success: success + success_inc,
-                        state: RunnerStateV1::Pass,
-                        on_pass,
-                        on_fail,
-                        on_finish,
-                    }
+                Self {
+                    progress,
+                    tree,
+                    target: target.to_string(),
+                    reporter,
+                    jobs,
+                    sandbox,
+                    prefetched,
+                    blocking,
+                    failure,
+                    tests,
+                    success,
+                    state,
+                    on_pass,
+                    on_fail,
+                    on_finish,
                 }
             }
             // A mandatory test failed. Displays a custom error message as
@@ -381,31 +456,28 @@ impl StateMachine for RunnerV1 {
             // error logging.
             RunnerStateV1::Fail { index_test, err } => {
                 progress.finish_and_clear();
-                progress.println(format!("\n⚠ Error: {}", err.red().bold()));
+
+                let status = RunStatus {
+                    success: false,
+                    passed: success,
+                    total: tests.len(),
+                    reason: Some(err),
+                };
+                reporter_finish(&mut reporter, &status, &progress);
 
                 on_fail(index_test);
                 on_finish();
 
-                if json_report_are_tests_passing(false, &mut client).is_err() {
-                    progress.println(
-                        "🚫 Failed to send test results to DotCodeSchool"
-                            .red()
-                            .bold()
-                            .to_string(),
-                    );
-                }
-
-                if json_report_close(&mut client).is_err() {
-                    progress.println(
-                        "🚫 Failed to close Websocket connection to DotCodeSchool".red().bold().to_string()
-                    );
-                }
-
                 Self {
                     progress,
                     tree,
                     target: target.to_string(),
-                    client,
+                    reporter,
+                    jobs,
+                    sandbox,
+                    prefetched,
+                    blocking,
+                    failure,
                     tests,
                     success,
                     state: RunnerStateV1::Finish,
@@ -421,39 +493,78 @@ impl StateMachine for RunnerV1 {
             // student.
             RunnerStateV1::Pass => {
                 progress.finish_and_clear();
-                let score = format!(
-                    "{:.2}",
-                    success as f64 / tests.len() as f64 * 100f64
-                );
 
-                progress.println(format!(
-                    "\n🏁 final score: {}%",
-                    score.green().bold()
-                ));
+                let status = RunStatus {
+                    success: true,
+                    passed: success,
+                    total: tests.len(),
+                    reason: None,
+                };
+                reporter_finish(&mut reporter, &status, &progress);
 
                 on_pass();
                 on_finish();
 
-                if json_report_are_tests_passing(true, &mut client).is_err() {
-                    progress.println(
-                        "🚫 Failed to send test results to DotCodeSchool"
-                            .red()
-                            .bold()
-                            .to_string(),
-                    );
+                Self {
+                    progress,
+                    tree,
+                    target: target.to_string(),
+                    reporter,
+                    jobs,
+                    sandbox,
+                    prefetched,
+                    blocking,
+                    failure,
+                    tests,
+                    success,
+                    state: RunnerStateV1::Finish,
+                    on_pass,
+                    on_fail,
+                    on_finish,
                 }
+            }
+            RunnerStateV1::Empty => {
+                progress.finish_and_clear();
+                progress.println("\n📭 No tests to run");
+                reporter_warn(reporter.close(), &progress);
 
-                if json_report_close(&mut client).is_err() {
-                    progress.println(
-                        "🚫 Failed to close Websocket connection to DotCodeSchool".red().bold().to_string()
-                    );
+                on_finish();
+
+                Self {
+                    progress,
+                    tree,
+                    target: target.to_string(),
+                    reporter,
+                    jobs,
+                    sandbox,
+                    prefetched,
+                    blocking,
+                    failure,
+                    tests,
+                    success,
+                    state: RunnerStateV1::Finish,
+                    on_pass,
+                    on_fail,
+                    on_finish,
                 }
+            }
+            // Stops without recording the result of the run.
+            RunnerStateV1::Interrupted => {
+                progress.finish_and_clear();
+                reporter_warn(reporter.close(), &progress);
+
+                on_finish();
 
                 Self {
                     progress,
                     tree,
                     target: target.to_string(),
-                    client,
+                    reporter,
+                    jobs,
+                    sandbox,
+                    prefetched,
+                    blocking,
+                    failure,
                     tests,
                     success,
                     state: RunnerStateV1::Finish,
@@ -467,7 +578,12 @@ impl StateMachine for RunnerV1 {
                 progress,
                 tree,
                 target: target.to_string(),
-                client,
+                reporter,
+                jobs,
+                sandbox,
+                prefetched,
+                blocking,
+                failure,
                 tests,
                 success,
                 state: RunnerStateV1::Finish,
@@ -483,202 +599,116 @@ impl StateMachine for RunnerV1 {
     }
 }
 
-fn test_pass(old: Option<&[u8]>) -> Option<Vec<u8>> {
-    let bytes = old?;
-    let mut test = TestState::decode(&mut &bytes[..]).ok()?;
+/// Runs `tests` concurrently in the `target` workspace, using at most `jobs`
+/// threads. Results are returned in the same order as `tests`.
+///
+/// No more tests are started once a mandatory test has failed, or when
+/// reaching a test which depends on a test in `blocking`, as such tests might
+/// be blocked and their results discarded. The first test is always run, and
+/// tests which were not started have no result.
+fn tests_run_parallel(
+    tests: &[(sled::IVec, TestState)],
+    target: &str,
+    sandbox: Option<&Sandbox>,
+    jobs: usize,
+    blocking: &HashMap<String, String>,
+) -> Vec<Option<TestResult>> {
+    let index_next = AtomicUsize::new(0);
+    let stop = AtomicBool::new(false);
+    let results = Mutex::new(
+        std::iter::repeat_with(|| None).take(tests.len()).collect::<Vec<_>>(),
+    );
 
-    test.passed = ValidationState::Pass;
+    std::thread::scope(|scope| {
+        for _ in 0..jobs.min(tests.len()) {
+            scope.spawn(|| loop {
+                let index = index_next.fetch_add(1, Ordering::Relaxed);
+                let Some((_, test)) = tests.get(index) else {
+                    break;
+                };
 
-    Some(test.encode())
-}
+                let blocked =
+                    test.depends_on.iter().any(|id| blocking.contains_key(id));
+                if index > 0 && (blocked || stop.load(Ordering::Relaxed)) {
+                    stop.store(true, Ordering::Relaxed);
+                    break;
+                }
 
-fn test_fail(old: Option<&[u8]>) -> Option<Vec<u8>> {
-    let bytes = old?;
-    let mut test = TestState::decode(&mut &bytes[..]).ok()?;
+                let result = test.run(target, sandbox);
+                if !test.optional && !matches!(result, TestResult::Pass(_)) {
+                    log::debug!("stopping batch after {} failed", test.name);
+                    stop.store(true, Ordering::Relaxed);
+                }
 
-    test.passed = ValidationState::Fail;
+                results.lock().unwrap()[index] = Some(result);
+            });
+        }
+    });
 
-    Some(test.encode())
+    results.into_inner().unwrap()
 }
 
-#[derive(Error, Debug)]
-enum RedisReportError {
-    #[error("failed to convert test result to JSON: {0}")]
-    JsonError(String),
-    #[error("failed to send report via websocket: {0}")]
-    WsError(String),
+/// Sends the final status of the run to every reporter and closes them.
+fn reporter_finish(
+    reporter: &mut Reporters,
+    status: &RunStatus,
+    progress: &ProgressBar,
+) {
+    reporter_warn(reporter.run_status(status), progress);
+    reporter_warn(reporter.close(), progress);
 }
 
-fn json_report_test(
-    result: RedisTestResultV1,
-    client: &mut WebSocket<MaybeTlsStream<TcpStream>>,
-    test: &TestState,
-    repo_name: &String,
-) -> Result<(), RedisReportError> {
-    #[cfg(debug_assertions)]
-    let json = serde_json::to_string_pretty(&result)
-        .map_err(|err| RedisReportError::JsonError(err.to_string()))?;
-
-    #[cfg(not(debug_assertions))]
-    let json = serde_json::to_string(&result)
-        .map_err(|err| RedisReportError::JsonError(err.to_string()))?;
-
-    log::debug!("Test result: {json}");
-
-    #[cfg(debug_assertions)]
-    let message = format!(
-        concat!(
-            "{{\n",
-            "  \"event_type\":",
-            "  \"log\",\n",
-            "  \"bytes\":",
-            "  \"{:?}\"\n",
-            "}}"
-        ),
-        json.as_bytes()
-    );
-
-    #[cfg(not(debug_assertions))]
-    let message = format!(
-        concat!(
-            "{{",
-            "\"event_type\":",
-            "\"log\",",
-            "\"bytes\":",
-            "\"{:?}\"",
-            "}}"
-        ),
-        json.as_bytes()
-    );
-
-    log::debug!("Sending message to redis: {message}");
-
-    client
-        .send(Message::Text(message))
-        .map_err(|err| RedisReportError::WsError(err.to_string()))?;
-
-    log::debug!("Message sent successfully");
-
-    // Get path info from test state
-    let [section_link, lesson_link, _, _] = &test.path[..] else {
-        return Ok(());
-    };
-
-    // Extract section and lesson names
-    let section_name = match section_link {
-        PathLink::Link(name) | PathLink::LinkOptional(name) => name.clone(),
-    };
-
-    let lesson_name = match lesson_link {
-        PathLink::Link(name) | PathLink::LinkOptional(name) => name.clone(),
-    };
-
-    // Use the lesson slug directly from the test state
-    // This is the slug defined in the tester-definition.yml file
-    let lesson_slug = test.lesson_slug.clone();
-
-    let test_log = TestLogEntry {
-        test_slug: test.slug.clone(),
-        passed: matches!(result.state, RedisTestState::Passed),
-        timestamp: chrono::Utc::now(),
-        section_name,
-        lesson_name,
-        lesson_slug,
-        test_name: test.name.clone(),
-        repo_name: repo_name.clone(),
-    };
-
-    // TODO: Send log entry to MongoDB using the backend endpoint
-    // BACKEND_URL/test-log
-    let url = format!("{}/test-log", crate::constants::BACKEND_URL);
-    match Client::new().post(&url).json(&test_log).send() {
-        Ok(response) => {
-            if response.status() == StatusCode::OK {
-                log::info!("Test log entry sent successfully");
-            } else {
-                log::error!(
-                    "Failed to send test log entry: {}",
-                    response.status()
-                );
-            }
-        }
-        Err(err) => {
-            log::error!("Failed to send test log entry: {}", err);
-        }
+/// Warns the user of a reporter which failed. Reporting failures never affect
+/// the outcome of a run.
+fn reporter_warn(result: Result<(), ReporterError>, progress: &ProgressBar) {
+    if let Err(e) = result {
+        progress.println(format!("🚫 {}", e.to_string().red().bold()));
     }
-
-    Ok(())
 }
 
-fn json_report_are_tests_passing(
-    status: bool,
-    client: &mut WebSocket<MaybeTlsStream<TcpStream>>,
-) -> Result<(), RedisReportError> {
-    #[cfg(debug_assertions)]
-    let message = format!(
-        concat!(
-            "{{\n",
-            "  \"event_type\":\n",
-            "  \"status\",\n",
-            "  \"success\": {}\n",
-            "}}"
-        ),
-        status
-    );
+fn test_pass(old: Option<&[u8]>) -> Option<Vec<u8>> {
+    let bytes = old?;
+    let mut test = TestState::decode(&mut &bytes[..]).ok()?;
 
-    #[cfg(not(debug_assertions))]
-    let message = format!(
-        concat!(
-            "{{",
-            "\"event_type\":",
-            "\"status\",",
-            "\"success\": {}",
-            "}}"
-        ),
-        status
-    );
+    test.passed = ValidationState::Pass;
 
-    log::debug!("Sending message to redis: {message}");
+    Some(test.encode())
+}
 
-    client
-        .send(Message::Text(message))
-        .map_err(|err| RedisReportError::WsError(err.to_string()))?;
+fn test_fail(old: Option<&[u8]>) -> Option<Vec<u8>> {
+    let bytes = old?;
+    let mut test = TestState::decode(&mut &bytes[..]).ok()?;
 
-    log::debug!("Message sent successfully");
+    test.passed = ValidationState::Fail;
 
-    Ok(())
+    Some(test.encode())
 }
 
-fn json_report_close(
-    client: &mut WebSocket<MaybeTlsStream<TcpStream>>,
-) -> Result<(), RedisReportError> {
-    log::debug!("Closing websocket connection");
-
-    #[cfg(debug_assertions)]
-    let message =
-        concat!("{\n", "  \"event_type\":", "  \"disconnect\"\n", "}")
-            .to_string();
+fn test_blocked(old: Option<&[u8]>) -> Option<Vec<u8>> {
+    let bytes = old?;
+    let mut test = TestState::decode(&mut &bytes[..]).ok()?;
 
-    #[cfg(not(debug_assertions))]
-    let message =
-        concat!("{", "\"event_type\":", "\"disconnect\"", "}").to_string();
+    test.passed = ValidationState::Blocked;
 
-    log::debug!("Sending message to redis: {message}");
+    Some(test.encode())
+}
 
-    client
-        .send(Message::Text(message))
-        .map_err(|err| RedisReportError::WsError(err.to_string()))?;
+fn test_timeout(old: Option<&[u8]>) -> Option<Vec<u8>> {
+    let bytes = old?;
+    let mut test = TestState::decode(&mut &bytes[..]).ok()?;
 
-    log::debug!("Websocket connection closed successfully");
+    test.passed = ValidationState::Timeout;
 
-    Ok(())
+    Some(test.encode())
 }
 
 pub struct RunnerV1Builder<A, B, C, D, E> {
     progress: A,
     target: B,
     tree: C,
-    client: D,
+    reporter: D,
+    jobs: usize,
+    sandbox: Option<Sandbox>,
     tests: E,
     success: u32,
     state: RunnerStateV1,
@@ -693,7 +723,9 @@ impl RunnerV1Builder<(), (), (), (), ()> {
             progress: (),
             target: (),
             tree: (),
-            client: (),
+            reporter: (),
+            jobs: 1,
+            sandbox: None,
             tests: (),
             success: 0,
             state: RunnerStateV1::Loaded,
@@ -714,7 +746,9 @@ impl<A, B, C, D, E> RunnerV1Builder<A, B, C, D, E> {
             progress,
             target: self.target,
             tree: self.tree,
-            client: self.client,
+            reporter: self.reporter,
+            jobs: self.jobs,
+            sandbox: self.sandbox,
             tests: self.tests,
             success: self.success,
             state: self.state,
@@ -729,7 +763,9 @@ impl<A, B, C, D, E> RunnerV1Builder<A, B, C, D, E> {
             progress: self.progress,
             target,
             tree: self.tree,
-            client: self.client,
+            reporter: self.reporter,
+            jobs: self.jobs,
+            sandbox: self.sandbox,
             tests: self.tests,
             success: self.success,
             state: self.state,
@@ -747,7 +783,9 @@ impl<A, B, C, D, E> RunnerV1Builder<A, B, C, D, E> {
             progress: self.progress,
             target: self.target,
             tree,
-            client: self.client,
+            reporter: self.reporter,
+            jobs: self.jobs,
+            sandbox: self.sandbox,
             tests: self.tests,
             success: self.success,
             state: self.state,
@@ -757,15 +795,17 @@ impl<A, B, C, D, E> RunnerV1Builder<A, B, C, D, E> {
         }
     }
 
-    pub fn client(
+    pub fn reporter(
         self,
-        client: WebSocket<MaybeTlsStream<TcpStream>>,
-    ) -> RunnerV1Builder<A, B, C, WebSocket<MaybeTlsStream<TcpStream>>, E> {
+        reporter: Reporters,
+    ) -> RunnerV1Builder<A, B, C, Reporters, E> {
         RunnerV1Builder {
             progress: self.progress,
             target: self.target,
             tree: self.tree,
-            client,
+            reporter,
+            jobs: self.jobs,
+            sandbox: self.sandbox,
             tests: self.tests,
             success: self.success,
             state: self.state,
@@ -783,7 +823,9 @@ impl<A, B, C, D, E> RunnerV1Builder<A, B, C, D, E> {
             progress: self.progress,
             target: self.target,
             tree: self.tree,
-            client: self.client,
+            reporter: self.reporter,
+            jobs: self.jobs,
+            sandbox: self.sandbox,
             tests,
             success: self.success,
             state: self.state,
@@ -793,6 +835,19 @@ impl<A, B, C, D, E> RunnerV1Builder<A, B, C, D, E> {
         }
     }
 
+    pub fn jobs(mut self, jobs: usize) -> RunnerV1Builder<A, B, C, D, E> {
+        self.jobs = jobs.max(1);
+        self
+    }
+
+    pub fn sandbox(
+        mut self,
+        sandbox: Option<Sandbox>,
+    ) -> RunnerV1Builder<A, B, C, D, E> {
+        self.sandbox = sandbox;
+        self
+    }
+
     pub fn on_pass<F1>(mut self, f: F1) -> RunnerV1Builder<A, B, C, D, E>
     where
         F1: Fn() + 'static,
@@ -823,7 +878,7 @@ impl
         ProgressBar,
         String,
         sled::Tree,
-        WebSocket<MaybeTlsStream<TcpStream>>,
+        Reporters,
         Vec<(sled::IVec, TestState)>,
     >
 {
@@ -832,7 +887,12 @@ impl
             progress: self.progress,
             target: self.target,
             tree: self.tree,
-            client: self.client,
+            reporter: self.reporter,
+            jobs: self.jobs,
+            sandbox: self.sandbox,
+            prefetched: HashMap::new(),
+            blocking: HashMap::new(),
+            failure: None,
             tests: self.tests,
             success: self.success,
             state: self.state,
@@ -842,3 +902,144 @@ impl
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, rc::Rc};
+
+    use super::*;
+    use crate::models::TesterDefinition;
+
+    /// Independent tests B and C each wait for the other to have started, and
+    /// time out if they are not run in the same batch. B then fails.
+    const COURSE: &str = r#"
+course_name: Course
+limits:
+  timeout: 10
+sections:
+  - name: Section
+    slug: section
+    lessons:
+      - name: Lesson
+        slug: lesson
+        duration: 1
+        tests:
+          - name: A
+            slug: a
+            optional: false
+            cmd: "true"
+            message_on_fail: ko
+            message_on_success: ok
+          - name: B
+            slug: b
+            optional: false
+            independent: true
+            shell: true
+            cmd: touch b; until [ -e c ]; do sleep 0.01; done; exit 1
+            message_on_fail: ko
+            message_on_success: ok
+          - name: C
+            slug: c
+            optional: false
+            independent: true
+            shell: true
+            cmd: touch c; until [ -e b ]; do sleep 0.01; done
+            message_on_fail: ko
+            message_on_success: ok
+          - name: D
+            slug: d
+            optional: false
+            cmd: "true"
+            message_on_fail: ko
+            message_on_success: ok
+"#;
+
+    /// Records the outcome of each test, by slug.
+    struct Outcomes(Rc<RefCell<Vec<(String, &'static str)>>>);
+
+    impl Reporter for Outcomes {
+        fn test_result(
+            &mut self,
+            _index: usize,
+            test: &TestState,
+            result: &TestResult,
+        ) -> Result<(), ReporterError> {
+            let outcome = match result {
+                TestResult::Pass(_) => "pass",
+                _ => "fail",
+            };
+            self.0.borrow_mut().push((test.slug.clone(), outcome));
+            Ok(())
+        }
+
+        fn test_blocked(
+            &mut self,
+            _index: usize,
+            test: &TestState,
+            _blocker: &str,
+        ) -> Result<(), ReporterError> {
+            self.0.borrow_mut().push((test.slug.clone(), "blocked"));
+            Ok(())
+        }
+
+        fn run_status(&mut self, _: &RunStatus) -> Result<(), ReporterError> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn runs_independent_tests_in_one_batch() {
+        let tester = serde_yaml::from_str::<TesterDefinition>(COURSE).unwrap();
+        let tests = tester
+            .list_tests()
+            .unwrap()
+            .into_iter()
+            .map(|(key, test)| (sled::IVec::from(key.as_bytes()), test))
+            .collect::<Vec<_>>();
+
+        let stages =
+            tests.iter().map(|(_, test)| test.stage).collect::<Vec<_>>();
+        assert_eq!(stages, [0, 1, 1, 2]);
+        assert_eq!(tests[3].1.depends_on, ["lesson/b", "lesson/c"]);
+
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let tree = db.open_tree(".").unwrap();
+        for (key, test) in tests.iter() {
+            tree.insert(key, test.encode()).unwrap();
+        }
+
+        let target = std::env::temp_dir()
+            .join(format!("dcs-runner-batch-{}", std::process::id()));
+        std::fs::create_dir_all(&target).unwrap();
+
+        let outcomes = Rc::new(RefCell::new(vec![]));
+        let mut reporter = Reporters::new();
+        reporter.push(Outcomes(outcomes.clone()));
+
+        let mut runner = RunnerV1Builder::new()
+            .progress(ProgressBar::hidden())
+            .target(target.display().to_string())
+            .tree(tree)
+            .reporter(reporter)
+            .jobs(2)
+            .tests(tests)
+            .build();
+        while !runner.is_finished() {
+            runner = runner.run();
+        }
+
+        std::fs::remove_dir_all(&target).unwrap();
+
+        // C does not depend on B and passes even though B failed, D depends on
+        // both and is blocked
+        assert_eq!(
+            *outcomes.borrow(),
+            [
+                ("a".to_string(), "pass"),
+                ("b".to_string(), "fail"),
+                ("c".to_string(), "pass"),
+                ("d".to_string(), "blocked"),
+            ]
+        );
+    }
+}
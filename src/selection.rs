@@ -0,0 +1,157 @@
+//! Selection of the tests to run from the command line.
+//!
+//! Tests are selected with patterns of the form `section/lesson/test`, where
+//! each part is either the slug or the name of a course element, compared
+//! without regard to case. Patterns can also address a single section, lesson
+//! or test, or any run of consecutive parts such as `lesson/test`. Parts may
+//! contain the glob wildcards `*`, `?` and `[...]`, which never match across a
+//! `/`:
+//!
+//! ```text
+//! intro                 every test in the `intro` section or lesson
+//! intro/*/*panic*       tests mentioning `panic` in any lesson of `intro`
+//! handles-overflow      a single test, by slug
+//! ```
+
+use globset::{Glob, GlobBuilder, GlobMatcher};
+use itertools::Itertools;
+use thiserror::Error;
+
+use crate::db::{PathLink, TestState};
+
+/// Maximum number of close candidates suggested when a pattern matches no
+/// tests.
+const CANDIDATES_MAX: usize = 3;
+/// Minimum similarity, between 0 and 1, for a test to be suggested.
+const CANDIDATES_CUTOFF: f32 = 0.6;
+
+#[derive(Error, Debug)]
+pub enum SelectionError {
+    #[error("invalid test pattern '{0}': {1}")]
+    PatternInvalid(String, String),
+    #[error("no tests matched '{0}'{}", candidates_format(.1))]
+    NoMatch(String, Vec<String>),
+}
+
+/// Keeps the `tests` matched by any of `patterns`, in order. Fails if any of
+/// the patterns does not match a single test.
+pub fn tests_select<T>(
+    tests: Vec<(T, TestState)>,
+    patterns: &[String],
+) -> Result<Vec<(T, TestState)>, SelectionError> {
+    let matchers = patterns
+        .iter()
+        .map(|pattern| pattern_compile(pattern))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let paths =
+        tests.iter().map(|(_, test)| test_paths(test)).collect::<Vec<_>>();
+
+    for (pattern, matcher) in patterns.iter().zip(&matchers) {
+        let matched =
+            paths.iter().flatten().any(|path| matcher.is_match(path.as_str()));
+
+        if !matched {
+            let candidates = candidates_close(pattern, &paths);
+            return Err(SelectionError::NoMatch(pattern.clone(), candidates));
+        }
+    }
+
+    let selected = tests
+        .into_iter()
+        .zip(paths)
+        .filter(|(_, paths)| {
+            matchers.iter().any(|matcher| {
+                paths.iter().any(|path| matcher.is_match(path.as_str()))
+            })
+        })
+        .map(|(test, _)| test)
+        .collect();
+
+    Ok(selected)
+}
+
+fn pattern_compile(pattern: &str) -> Result<GlobMatcher, SelectionError> {
+    let pattern = pattern.trim_matches('/');
+
+    GlobBuilder::new(pattern)
+        .case_insensitive(true)
+        .literal_separator(true)
+        .build()
+        .map(|glob: Glob| glob.compile_matcher())
+        .map_err(|err| {
+            SelectionError::PatternInvalid(pattern.to_string(), err.to_string())
+        })
+}
+
+/// Every path a test can be addressed by: each run of consecutive parts of
+/// `section/lesson/test`, with each part as either a slug or a name.
+fn test_paths(test: &TestState) -> Vec<String> {
+    let parts = test_parts(test);
+
+    (0..parts.len())
+        .flat_map(|start| {
+            (start + 1..=parts.len()).map(move |end| (start, end))
+        })
+        .flat_map(|(start, end)| {
+            parts[start..end]
+                .iter()
+                .map(|(slug, name)| [slug.as_str(), name.as_str()])
+                .multi_cartesian_product()
+                .map(|path| path.join("/").to_lowercase())
+        })
+        .unique()
+        .collect()
+}
+
+/// Slug and name of the section, lesson and test a test belongs to.
+fn test_parts(test: &TestState) -> [(String, String); 3] {
+    let name = |index: usize| match test.path.get(index) {
+        Some(PathLink::Link(name) | PathLink::LinkOptional(name)) => {
+            name.clone()
+        }
+        None => String::default(),
+    };
+
+    [
+        (test.section_slug.clone(), name(0)),
+        (test.lesson_slug.clone(), name(1)),
+        (test.slug.clone(), test.name.clone()),
+    ]
+}
+
+/// Paths close to `pattern`, among those with as many parts as `pattern`.
+fn candidates_close(pattern: &str, paths: &[Vec<String>]) -> Vec<String> {
+    let pattern = pattern.trim_matches('/').to_lowercase();
+    let depth = pattern.split('/').count();
+
+    let candidates = paths
+        .iter()
+        .flatten()
+        .filter(|path| path.split('/').count() == depth)
+        .map(String::as_str)
+        .unique()
+        .collect::<Vec<_>>();
+
+    similar::get_close_matches(
+        pattern.as_str(),
+        &candidates,
+        CANDIDATES_MAX,
+        CANDIDATES_CUTOFF,
+    )
+    .into_iter()
+    .map(str::to_string)
+    .collect()
+}
+
+fn candidates_format(candidates: &[String]) -> String {
+    if candidates.is_empty() {
+        String::default()
+    } else {
+        let candidates = candidates
+            .iter()
+            .map(|candidate| format!("'{candidate}'"))
+            .join(", ");
+        format!(", did you mean {candidates}?")
+    }
+}
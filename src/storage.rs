@@ -0,0 +1,473 @@
+//! Location of the database and log kept for the student's repository.
+//!
+//! State is stored outside of the repository, so that it is neither copied to
+//! the tester workspace nor committed by mistake, in a directory of the user's
+//! data directory (`$XDG_DATA_HOME/dotcodeschool` on Linux):
+//!
+//! ```text
+//! dotcodeschool/repos/<repo name>-<repo id>/
+//! ├── db
+//! ├── db.lock
+//! └── dcs.log
+//! ```
+//!
+//! The id of a repository is derived from the URL of its `origin` remote,
+//! which the course is resolved from, so that separate clones of the same
+//! repository share their progress and different repositories with the same
+//! name do not. Repositories without a remote are identified by their path.
+//! When running from a local tester definition, the id also includes the name
+//! of its course, so that courses tested offline in the same repository do not
+//! share their progress.
+//!
+//! A database left at [`PATH_DB`] and a log left at [`LOG`] in the repository
+//! by earlier versions of the CLI are moved to the data directory the first
+//! time it is used.
+
+use std::path::{Path, PathBuf};
+
+use thiserror::Error;
+
+use crate::{
+    constants::LOG,
+    db::{hash_content, PATH_DB},
+    parsing::load_tester_local,
+};
+
+const DIR_DATA: &str = "dotcodeschool";
+const DIR_REPOS: &str = "repos";
+const FILE_DB: &str = "db";
+const FILE_LOG: &str = "dcs.log";
+/// Number of characters of the repository id used in its directory name.
+const REPO_ID_LEN: usize = 16;
+
+#[derive(Error, Debug)]
+pub enum StorageError {
+    #[error("failed to create data directory at '{0}': {1}")]
+    DirCreate(String, String),
+    #[error("failed to move database from '{0}' to '{1}': {2}")]
+    DbMove(String, String, String),
+    #[error("failed to move log from '{0}' to '{1}': {2}")]
+    LogMove(String, String, String),
+}
+
+/// Paths to the state kept for the student's repository.
+#[derive(Debug, Clone)]
+pub struct Storage {
+    path_db: PathBuf,
+    path_log: PathBuf,
+    /// Root of the student's repository, where earlier versions of the CLI
+    /// kept their state.
+    root: PathBuf,
+    /// Whether the database is at the default location, as opposed to one set
+    /// with `--db`.
+    default: bool,
+}
+
+impl Storage {
+    /// Resolves where state is stored for the repository in the current
+    /// directory.
+    ///
+    /// * `path_db`: value of the `--db` flag, if any.
+    /// * `path_tester`: local tester definition, if running offline.
+    pub fn locate(path_db: Option<&str>, path_tester: Option<&str>) -> Self {
+        let root = Self::repo_root();
+        let dir = Self::dir_repo(&root, path_tester);
+
+        let path_log = match &dir {
+            Some(dir) => dir.join(FILE_LOG),
+            None => root.join(LOG),
+        };
+
+        match (path_db, dir) {
+            (Some(path_db), _) => Self {
+                path_db: PathBuf::from(path_db),
+                path_log,
+                root,
+                default: false,
+            },
+            (None, Some(dir)) => Self {
+                path_db: dir.join(FILE_DB),
+                path_log,
+                root,
+                default: true,
+            },
+            // Without a data directory, state is kept in the repository as it
+            // used to be
+            (None, None) => Self {
+                path_db: root.join(PATH_DB),
+                path_log,
+                root,
+                default: true,
+            },
+        }
+    }
+
+    /// Creates the data directory.
+    pub fn init(&self) -> Result<(), StorageError> {
+        for path in [&self.path_log, &self.path_db] {
+            let Some(dir) = path.parent() else {
+                continue;
+            };
+
+            std::fs::create_dir_all(dir).map_err(|err| {
+                StorageError::DirCreate(
+                    dir.display().to_string(),
+                    err.to_string(),
+                )
+            })?;
+        }
+
+        Ok(())
+    }
+
+    /// Moves over the database and log of earlier versions of the CLI, if any.
+    /// Must be called with the [`RunLock`](crate::lock::RunLock) held, so that
+    /// concurrent runs do not both try to move them.
+    pub fn migrate(&self) -> Result<(), StorageError> {
+        self.log_migrate()?;
+
+        let path_legacy = self.root.join(PATH_DB);
+        if !self.default
+            || self.path_db == path_legacy
+            || !Self::is_db(&path_legacy)
+        {
+            return Ok(());
+        }
+
+        if self.path_db.exists() {
+            log::debug!(
+                "not moving database at '{}', '{}' already exists",
+                path_legacy.display(),
+                self.path_db.display()
+            );
+            return Ok(());
+        }
+
+        log::debug!(
+            "moving database from '{}' to '{}'",
+            path_legacy.display(),
+            self.path_db.display()
+        );
+
+        dir_move(&path_legacy, &self.path_db).map_err(|err| {
+            StorageError::DbMove(
+                path_legacy.display().to_string(),
+                self.path_db.display().to_string(),
+                err.to_string(),
+            )
+        })
+    }
+
+    /// Moves the log of earlier versions of the CLI to the data directory. It
+    /// is only kept if there is no log there yet.
+    fn log_migrate(&self) -> Result<(), StorageError> {
+        let path_legacy = self.root.join(LOG);
+        if self.path_log == path_legacy || !path_legacy.is_file() {
+            return Ok(());
+        }
+
+        let result = match self.path_log.exists() {
+            true => std::fs::remove_file(&path_legacy),
+            false => {
+                std::fs::rename(&path_legacy, &self.path_log).or_else(|_| {
+                    std::fs::copy(&path_legacy, &self.path_log)?;
+                    std::fs::remove_file(&path_legacy)
+                })
+            }
+        };
+
+        result.map_err(|err| {
+            StorageError::LogMove(
+                path_legacy.display().to_string(),
+                self.path_log.display().to_string(),
+                err.to_string(),
+            )
+        })
+    }
+
+    pub fn path_db(&self) -> String {
+        self.path_db.display().to_string()
+    }
+
+    pub fn path_log(&self) -> &Path {
+        &self.path_log
+    }
+
+    /// Lock file guarding the database, see [`RunLock`](crate::lock::RunLock).
+    pub fn path_lock(&self) -> PathBuf {
+        let mut path = self.path_db.clone().into_os_string();
+        path.push(".lock");
+
+        PathBuf::from(path)
+    }
+
+    /// Directory holding the state of the repository at `root`, or `None` if
+    /// the user has no data directory.
+    fn dir_repo(root: &Path, path_tester: Option<&str>) -> Option<PathBuf> {
+        let (name, identity) = Self::repo_identity(root, path_tester);
+        let id = hash_content(&[identity.as_bytes()]);
+
+        dirs::data_dir().map(|dir| {
+            dir.join(DIR_DATA)
+                .join(DIR_REPOS)
+                .join(format!("{name}-{}", &id[..REPO_ID_LEN]))
+        })
+    }
+
+    /// Root of the repository in the current directory, or the current
+    /// directory if it is not part of a repository.
+    fn repo_root() -> PathBuf {
+        let cwd = std::env::current_dir()
+            .and_then(|dir| dir.canonicalize())
+            .unwrap_or_default();
+
+        git2::Repository::discover(".")
+            .ok()
+            .and_then(|repo| repo.workdir()?.canonicalize().ok())
+            .unwrap_or(cwd)
+    }
+
+    /// Name of the repository at `root`, along with a string which identifies
+    /// it and the course tested in it.
+    fn repo_identity(
+        root: &Path,
+        path_tester: Option<&str>,
+    ) -> (String, String) {
+        let (name, identity) = Self::repo_url_identity(root);
+
+        // Online, the course is resolved from the name of the repository,
+        // which is already part of its identity
+        let course = path_tester
+            .and_then(|path| load_tester_local(path).ok())
+            .map(|tester| tester.course_name);
+
+        match course {
+            Some(course) => (name, format!("{identity}\n{course}")),
+            None => (name, identity),
+        }
+    }
+
+    /// Name of the repository at `root`, along with its remote URL or its path
+    /// if it has none.
+    fn repo_url_identity(root: &Path) -> (String, String) {
+        let url = git2::Repository::open(root).ok().and_then(|repo| {
+            let remote = repo.find_remote("origin").ok()?;
+            remote.url().map(|url| url.trim_end_matches('/').to_string())
+        });
+
+        match url {
+            Some(url) => {
+                let url = url.trim_end_matches(".git").to_string();
+                let name = url
+                    .rsplit(['/', ':'])
+                    .next()
+                    .unwrap_or_default()
+                    .to_string();
+                (name, url)
+            }
+            None => {
+                let name = root
+                    .file_name()
+                    .map(|name| name.to_string_lossy().to_string())
+                    .unwrap_or_default();
+                (name, root.display().to_string())
+            }
+        }
+    }
+
+    /// Whether `path` is a database, and not a directory of the student's
+    /// which happens to have the same name.
+    fn is_db(path: &Path) -> bool {
+        path.join("conf").is_file() && path.join("db").is_file()
+    }
+}
+
+/// Moves directory `from` to `to`, copying it over if they are on different
+/// filesystems.
+fn dir_move(from: &Path, to: &Path) -> Result<(), std::io::Error> {
+    if std::fs::rename(from, to).is_ok() {
+        return Ok(());
+    }
+
+    dir_copy(from, to)?;
+    std::fs::remove_dir_all(from)
+}
+
+fn dir_copy(from: &Path, to: &Path) -> Result<(), std::io::Error> {
+    std::fs::create_dir_all(to)?;
+
+    for entry in std::fs::read_dir(from)? {
+        let entry = entry?;
+        let path = to.join(entry.file_name());
+
+        if entry.file_type()?.is_dir() {
+            dir_copy(&entry.path(), &path)?;
+        } else {
+            std::fs::copy(entry.path(), path)?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Empty directory unique to this test.
+    fn dir_test(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir()
+            .join(format!("dcs-storage-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+
+        dir
+    }
+
+    fn tester_write(path: &Path, course: &str) {
+        let tester = format!(
+            "course_name: {course}\nsections:\n  - name: Section\n    slug: \
+             section\n    lessons:\n      - name: Lesson\n        slug: \
+             lesson\n        duration: 1\n"
+        );
+        std::fs::write(path, tester).unwrap();
+    }
+
+    /// Storage of a repository at `root`, with its data directory in `data`.
+    fn storage(root: &Path, data: &Path) -> Storage {
+        Storage {
+            path_db: data.join(FILE_DB),
+            path_log: data.join(FILE_LOG),
+            root: root.to_path_buf(),
+            default: true,
+        }
+    }
+
+    #[test]
+    fn identifies_repositories_by_remote() {
+        let root = dir_test("remote");
+        let repo = git2::Repository::init(&root).unwrap();
+        repo.remote("origin", "git@github.com:student/rust-state-machine.git/")
+            .unwrap();
+
+        let (name, identity) = Storage::repo_identity(&root, None);
+        assert_eq!(name, "rust-state-machine");
+        assert_eq!(identity, "git@github.com:student/rust-state-machine");
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn identifies_offline_courses() {
+        let root = dir_test("offline");
+        let path_a = root.join("a.yml");
+        let path_b = root.join("b.yml");
+        tester_write(&path_a, "A");
+        tester_write(&path_b, "B");
+
+        // Without a remote, the repository is identified by its path
+        let identity =
+            |path: &Path| Storage::repo_identity(&root, path.to_str());
+        let (name, identity_a) = identity(&path_a);
+        let (_, identity_b) = identity(&path_b);
+        let (_, identity_online) = Storage::repo_identity(&root, None);
+
+        assert_eq!(root.file_name().unwrap().to_str(), Some(name.as_str()));
+        assert_eq!(identity_online, root.display().to_string());
+        assert_eq!(identity_a, format!("{identity_online}\nA"));
+        assert_eq!(identity_b, format!("{identity_online}\nB"));
+
+        // The same course is always stored in the same place
+        assert_eq!(
+            Storage::dir_repo(&root, path_a.to_str()),
+            Storage::dir_repo(&root, path_a.to_str())
+        );
+        assert_ne!(
+            Storage::dir_repo(&root, path_a.to_str()),
+            Storage::dir_repo(&root, path_b.to_str())
+        );
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn moves_legacy_state() {
+        let root = dir_test("legacy");
+        let data = root.join("data");
+        let storage = storage(&root, &data);
+
+        let db = sled::open(root.join(PATH_DB)).unwrap();
+        db.insert("key", "value").unwrap();
+        db.flush().unwrap();
+        drop(db);
+        std::fs::write(root.join(LOG), "legacy").unwrap();
+
+        storage.init().unwrap();
+        storage.migrate().unwrap();
+
+        assert!(!root.join(PATH_DB).exists());
+        assert!(!root.join(LOG).exists());
+        let db = sled::open(storage.path_db()).unwrap();
+        assert_eq!(db.get("key").unwrap().as_deref(), Some(&b"value"[..]));
+        drop(db);
+        assert_eq!(
+            std::fs::read_to_string(storage.path_log()).unwrap(),
+            "legacy"
+        );
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn keeps_existing_state() {
+        let root = dir_test("existing");
+        let data = root.join("data");
+        let storage = storage(&root, &data);
+        storage.init().unwrap();
+
+        std::fs::write(storage.path_log(), "current").unwrap();
+        std::fs::write(root.join(LOG), "legacy").unwrap();
+
+        // A directory of the student's which is not a database
+        std::fs::create_dir_all(root.join(PATH_DB)).unwrap();
+        std::fs::write(root.join(PATH_DB).join("notes"), "").unwrap();
+
+        storage.migrate().unwrap();
+
+        assert_eq!(
+            std::fs::read_to_string(storage.path_log()).unwrap(),
+            "current"
+        );
+        assert!(!root.join(LOG).exists());
+        assert!(root.join(PATH_DB).join("notes").exists());
+        assert!(!data.join(FILE_DB).exists());
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn copies_directories_which_cannot_be_renamed() {
+        let root = dir_test("copy");
+        let from = root.join("from");
+        let to = root.join("to");
+        std::fs::create_dir_all(from.join("nested")).unwrap();
+        std::fs::write(from.join("conf"), "conf").unwrap();
+        std::fs::write(from.join("nested").join("blob"), "blob").unwrap();
+
+        // Renaming onto a directory which is not empty fails, as renaming
+        // across filesystems does
+        std::fs::create_dir_all(&to).unwrap();
+        std::fs::write(to.join("other"), "").unwrap();
+
+        dir_move(&from, &to).unwrap();
+
+        assert!(!from.exists());
+        assert_eq!(std::fs::read_to_string(to.join("conf")).unwrap(), "conf");
+        assert_eq!(
+            std::fs::read_to_string(to.join("nested").join("blob")).unwrap(),
+            "blob"
+        );
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
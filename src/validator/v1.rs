@@ -1,11 +1,19 @@
+use blake2::{
+    digest::{Update, VariableOutput},
+    Blake2bVar,
+};
 use colored::Colorize;
 use indicatif::ProgressBar;
 
 use crate::{
-    db::hash, models::TesterDefinition, monitor::StateMachine,
+    event::{self, Event},
+    models::TesterDefinition,
+    monitor::StateMachine,
     parsing::v1::JsonCourseV1,
 };
 
+const HASH_SIZE: usize = 2;
+
 #[derive(PartialEq, Eq, Debug)]
 pub enum ValidatorStateV1 {
     Loaded,
@@ -98,6 +106,11 @@ impl StateMachine for ValidatorV1 {
                     hash(&[&course.name, &section.name, &lesson.name,])
                 );
                 if slug_expected != lesson.slug {
+                    event::emit(Event::ValidationError {
+                        path: format!("{}/{}", section.name, lesson.name),
+                        slug: lesson.slug.clone(),
+                        message: format!("expected slug '{slug_expected}'"),
+                    });
                     progress.println(format!(
                         "  ╰─{}: {} ❌",
                         lesson.name.red().bold(),
@@ -188,7 +201,42 @@ impl StateMachine for ValidatorV1 {
                         &test.name,
                     ])
                 );
-                if slug_expected != test.slug {
+                let error = if slug_expected != test.slug {
+                    Some((
+                        format!("expected slug '{slug_expected}'"),
+                        format!(
+                            "Invalid slug: '{}', expected '{}'",
+                            test.slug, slug_expected
+                        ),
+                    ))
+                } else if let Some(path) = test.path_outside_workspace() {
+                    Some((
+                        format!("path '{path}' leaves the tester workspace"),
+                        format!("Invalid path for '{}': '{path}'", test.name),
+                    ))
+                } else if let Some((regex, err)) = test.regex_invalid() {
+                    Some((
+                        format!("invalid regex '{regex}': {err}"),
+                        format!("Invalid regex for '{}': {err}", test.name),
+                    ))
+                } else if let Err(err) = test.cmd.argv(test.shell) {
+                    Some((
+                        err.to_string(),
+                        format!("Invalid command for '{}': {err}", test.name),
+                    ))
+                } else {
+                    None
+                };
+
+                if let Some((message, reason)) = error {
+                    event::emit(Event::ValidationError {
+                        path: format!(
+                            "{}/{}/{}",
+                            section.name, lesson.name, test.name
+                        ),
+                        slug: test.slug.clone(),
+                        message,
+                    });
                     progress.println(format!(
                         "      ╰─{}: {} ❌",
                         test.name.red().bold(),
@@ -197,21 +245,34 @@ impl StateMachine for ValidatorV1 {
 
                     Self {
                         progress,
-                        state: ValidatorStateV1::Fail {
-                            reason: format!(
-                                "Invalid slug: '{}', expected '{}'",
-                                test.slug, slug_expected
-                            ),
-                        },
+                        state: ValidatorStateV1::Fail { reason },
                         course,
                         tester,
                     }
                 } else {
-                    progress.println(format!(
-                        "      ╰─{}: {} ✅",
-                        test.name.green().bold(),
-                        test.slug.white()
-                    ));
+                    match test.cmd.warning(test.shell) {
+                        Some(message) => {
+                            progress.println(format!(
+                                "      ╰─{}: {} ⚠ {}",
+                                test.name.yellow().bold(),
+                                test.slug.white(),
+                                message.yellow()
+                            ));
+                            event::emit(Event::ValidationWarning {
+                                path: format!(
+                                    "{}/{}/{}",
+                                    section.name, lesson.name, test.name
+                                ),
+                                slug: test.slug.clone(),
+                                message,
+                            });
+                        }
+                        None => progress.println(format!(
+                            "      ╰─{}: {} ✅",
+                            test.name.green().bold(),
+                            test.slug.white()
+                        )),
+                    }
 
                     progress.inc(1);
 
@@ -269,6 +330,7 @@ impl StateMachine for ValidatorV1 {
             ValidatorStateV1::Fail { reason } => {
                 progress.finish_and_clear();
                 progress.println(format!("\n⚠ Error: {}", reason.red().bold()));
+                event::emit(Event::ValidationFinished { valid: false });
 
                 Self {
                     progress,
@@ -282,6 +344,7 @@ impl StateMachine for ValidatorV1 {
                 progress.println(
                     "\n🏁 Course format is valid".green().bold().to_string(),
                 );
+                event::emit(Event::ValidationFinished { valid: true });
 
                 Self {
                     progress,
@@ -303,3 +366,17 @@ impl StateMachine for ValidatorV1 {
         self.state == ValidatorStateV1::Finish
     }
 }
+
+/// Slug expected for a lesson or test, derived from its name and the names of
+/// the course, section and lesson it is part of.
+fn hash(words: &[&str]) -> String {
+    let phrase = words.join("");
+
+    let mut hasher = Blake2bVar::new(HASH_SIZE).unwrap();
+    let mut hash = [0; HASH_SIZE];
+
+    hasher.update(phrase.as_bytes());
+    hasher.finalize_variable(&mut hash).unwrap();
+
+    hex::encode(hash)
+}